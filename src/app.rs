@@ -0,0 +1,921 @@
+use crate::game::Game;
+use crate::input::{InputEvent, InputRecording, InputSystem, Replay};
+use crate::pong::GameConfig;
+use crate::rng::Rng;
+use core::panic;
+use log::info;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::{cell::RefCell, sync::Mutex};
+use wasm_bindgen::prelude::*;
+use web_sys::{js_sys, HtmlCanvasElement, Window};
+use winit::dpi::PhysicalSize;
+use winit::event::{DeviceEvent, DeviceId, ElementState, KeyEvent, TouchPhase};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window;
+use winit::{
+    application::ApplicationHandler,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, EventLoop},
+    platform::web::WindowExtWebSys,
+    window::{Window as WinitWindow, WindowId},
+};
+
+use crate::audio::AudioSystem;
+use crate::renderer::{RendererInitError, RenderingSystem};
+
+#[wasm_bindgen(start)]
+pub fn main() {
+    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    console_log::init_with_level(log::Level::Debug).expect("Couldn't initialize logger");
+
+    wasm_bindgen_futures::spawn_local(run());
+}
+
+thread_local! {
+    // Set from JS with set_frame_callback, called after every presented frame
+    static FRAME_CALLBACK: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+    // Copied from the frame timer every frame, read from JS with fps
+    static FPS: Cell<f32> = const { Cell::new(0.0) };
+}
+
+/// The smoothed frame rate, see `FrameTimer::fps`. 0 until the second
+/// frame, and it holds still while the tab is paused.
+#[wasm_bindgen]
+pub fn fps() -> f32 {
+    FPS.get()
+}
+
+/// Registers `callback` to be called as `callback(frame, deltaTime)` after
+/// every frame is presented, with the frame number counting from 0 and the
+/// frame's duration in seconds. Handy for keeping DOM overlays in sync with
+/// the canvas. Pass `undefined` to remove it.
+#[wasm_bindgen]
+pub fn set_frame_callback(callback: Option<js_sys::Function>) {
+    FRAME_CALLBACK.with(|slot| *slot.borrow_mut() = callback);
+}
+
+fn notify_frame_complete(frame: u64, delta_time: f32) {
+    FRAME_CALLBACK.with(|slot| {
+        if let Some(callback) = &*slot.borrow() {
+            let result = callback.call2(
+                &JsValue::NULL,
+                &JsValue::from_f64(frame as f64),
+                &JsValue::from_f64(delta_time as f64),
+            );
+            // A broken overlay shouldn't take the game down with it
+            if let Err(err) = result {
+                log::error!("Frame callback threw: {:?}", err);
+            }
+        }
+    });
+}
+
+// What start_recording and load_replay asked for
+enum ReplayRequest {
+    Record,
+    Play(InputRecording),
+}
+
+// Recording and playback of match input, driven from JS by the functions below
+struct ReplayState {
+    // Started on the next frame, the exports can't reach the game
+    requested: Option<ReplayRequest>,
+    // Input of every fixed step since recording started
+    recording: Option<InputRecording>,
+    replay: Option<Replay>,
+}
+
+impl ReplayState {
+    // Restarts the match for a requested recording or replay
+    fn start_requested(&mut self, game: &mut Game) {
+        match self.requested.take() {
+            Some(ReplayRequest::Record) => {
+                let seed = Rng::time_seed();
+                game.restart_with_seed(seed);
+                self.replay = None;
+                self.recording = Some(InputRecording::new(seed));
+            }
+            Some(ReplayRequest::Play(recording)) => {
+                game.restart_with_seed(recording.seed());
+                self.recording = None;
+                self.replay = Some(Replay::new(recording));
+            }
+            None => {}
+        }
+    }
+
+    // Runs one fixed step on the replayed input while there is any left,
+    // recording the live input otherwise
+    fn step(&mut self, game: &mut Game, input: &InputSystem, audio: &mut AudioSystem) {
+        let replayed = self
+            .replay
+            .as_mut()
+            .and_then(|replay| replay.next_input(input));
+        match replayed {
+            Some(replay_input) => game.update(replay_input, audio, FIXED_TIMESTEP),
+            None => {
+                if self.replay.take().is_some() {
+                    info!("Replay finished");
+                }
+                if let Some(recording) = &mut self.recording {
+                    recording.record(input);
+                }
+                game.update(input, audio, FIXED_TIMESTEP);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static REPLAY: RefCell<ReplayState> = const {
+        RefCell::new(ReplayState {
+            requested: None,
+            recording: None,
+            replay: None,
+        })
+    };
+}
+
+/// Starts a new match on the next frame and records its input until
+/// `stop_recording`. The match is served from a fresh seed, which the
+/// recording keeps. Keys already held when it starts replay as released.
+#[wasm_bindgen]
+pub fn start_recording() {
+    REPLAY.with_borrow_mut(|replay| replay.requested = Some(ReplayRequest::Record));
+}
+
+/// Stops recording and returns the input as JSON, for `load_replay`, or
+/// `undefined` if nothing was being recorded.
+#[wasm_bindgen]
+pub fn stop_recording() -> Option<String> {
+    REPLAY.with_borrow_mut(|replay| {
+        if matches!(replay.requested, Some(ReplayRequest::Record)) {
+            replay.requested = None;
+        }
+        replay.recording.take().map(|recording| recording.to_json())
+    })
+}
+
+#[wasm_bindgen]
+pub fn is_recording() -> bool {
+    REPLAY.with_borrow(|replay| replay.recording.is_some())
+}
+
+/// Restarts the match on the next frame with the seed of `json`, a recording
+/// from `stop_recording`, and feeds the game its input instead of the live
+/// one until it runs out. Stops any recording in progress. Returns false,
+/// with the reason logged, if `json` isn't a recording.
+#[wasm_bindgen]
+pub fn load_replay(json: &str) -> bool {
+    let Some(recording) = InputRecording::from_json(json) else {
+        return false;
+    };
+    REPLAY.with_borrow_mut(|replay| replay.requested = Some(ReplayRequest::Play(recording)));
+    true
+}
+
+#[wasm_bindgen]
+pub fn is_replaying() -> bool {
+    REPLAY.with_borrow(|replay| replay.replay.is_some())
+}
+
+async fn run() {
+    let event_loop = EventLoop::new().unwrap();
+    let mut app = WebApp::new(EngineConfig::from_page());
+
+    event_loop.run_app(&mut app).unwrap();
+}
+
+/// The page elements and window title an engine instance uses.
+///
+/// Mark a container with `data-webengine` to mount into it. Each instance of
+/// the module claims the first container nobody has mounted into yet, so
+/// loading the module twice puts two engines on one page. The container can
+/// name its status element with `data-webengine-status`, otherwise it is the
+/// container's id followed by `-status`. It can also set:
+///
+/// - `data-webengine-title`, the window title
+/// - `data-webengine-target-fps`, a frame rate cap
+/// - `data-webengine-max-delta`, the longest frame in seconds the game is
+///   stepped by, 0.1 by default
+/// - `data-webengine-pointer-lock`, to lock the pointer when the canvas is clicked
+/// - `data-webengine-custom-cursor`, to hide the OS cursor for one the game draws
+struct EngineConfig {
+    container_id: String,
+    status_id: String,
+    title: String,
+    // See WebApp::set_target_fps
+    target_fps: Option<f32>,
+    // See FrameTimer::set_max_delta_time
+    max_delta_time: Option<f32>,
+    // See WebApp::set_pointer_lock_on_click
+    pointer_lock_on_click: bool,
+    // See WebApp::set_custom_cursor
+    custom_cursor: bool,
+}
+
+impl EngineConfig {
+    // Marks containers that already have an engine in them
+    const MOUNTED_ATTRIBUTE: &'static str = "data-webengine-mounted";
+
+    fn from_page() -> Self {
+        let mut config = Self::default();
+        let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+            return config;
+        };
+        let Ok(Some(container)) = document.query_selector(&format!(
+            "[data-webengine]:not([{}])",
+            Self::MOUNTED_ATTRIBUTE
+        )) else {
+            return config; // No marked container, use the default ids
+        };
+        let _ = container.set_attribute(Self::MOUNTED_ATTRIBUTE, "");
+
+        if container.id().is_empty() {
+            // The canvas is looked up by id later, so give it a unique one
+            let id = (0..)
+                .map(|index| format!("{}-{}", config.container_id, index))
+                .find(|id| document.get_element_by_id(id).is_none())
+                .unwrap();
+            container.set_id(&id);
+        }
+        config.container_id = container.id();
+        // Two engines sharing the page-wide default would fight over one element
+        config.status_id = container
+            .get_attribute("data-webengine-status")
+            .unwrap_or_else(|| format!("{}-status", config.container_id));
+        if let Some(title) = container.get_attribute("data-webengine-title") {
+            config.title = title;
+        }
+        if let Some(target_fps) = container.get_attribute("data-webengine-target-fps") {
+            match target_fps.trim().parse() {
+                Ok(target_fps) => config.target_fps = Some(target_fps),
+                Err(err) => log::warn!("Ignoring target fps {:?}: {}", target_fps, err),
+            }
+        }
+        if let Some(max_delta) = container.get_attribute("data-webengine-max-delta") {
+            match max_delta.trim().parse() {
+                Ok(max_delta) => config.max_delta_time = Some(max_delta),
+                Err(err) => log::warn!("Ignoring max delta {:?}: {}", max_delta, err),
+            }
+        }
+        config.pointer_lock_on_click = container.has_attribute("data-webengine-pointer-lock");
+        config.custom_cursor = container.has_attribute("data-webengine-custom-cursor");
+        config
+    }
+
+    fn status_element(&self) -> Option<web_sys::Element> {
+        web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.get_element_by_id(&self.status_id))
+    }
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            container_id: "webengine-container".to_string(),
+            status_id: "status".to_string(),
+            title: "WebEngine".to_string(),
+            target_fps: None,
+            max_delta_time: None,
+            pointer_lock_on_click: false,
+            custom_cursor: false,
+        }
+    }
+}
+
+enum AppState {
+    Loading {
+        game: Arc<Mutex<Option<Game>>>,
+        renderer: Arc<Mutex<Option<RenderingSystem>>>,
+        window: Arc<Mutex<Option<Arc<WinitWindow>>>>,
+        audio: Arc<Mutex<Option<AudioSystem>>>,
+        // Set if the renderer couldn't start, nothing else will load then
+        error: Arc<Mutex<Option<RendererInitError>>>,
+    },
+    // Loading failed and the error has been shown to the user
+    Failed,
+    Loaded {
+        game: Game,
+        renderer: RenderingSystem,
+        window: Arc<WinitWindow>,
+        input: InputSystem,
+        audio: AudioSystem,
+    },
+}
+
+impl AppState {
+    fn is_loading(&self) -> bool {
+        matches!(self, AppState::Loading { .. })
+    }
+
+    fn is_loaded(&self) -> bool {
+        matches!(self, AppState::Loaded { .. })
+    }
+
+    // Mutably advances the state in place, returns true if advancement happened
+    fn advance_in_place(&mut self, config: &EngineConfig) -> bool {
+        match self {
+            AppState::Loading {
+                game,
+                renderer,
+                window,
+                audio,
+                error,
+            } => {
+                let failure = error.lock().unwrap().take();
+                if let Some(error) = failure {
+                    show_load_error(&error, config);
+                    *self = AppState::Failed;
+                    return true;
+                }
+
+                // Check if all components are ready
+                let renderer_ready = renderer.lock().unwrap().is_some();
+                let game_ready = game.lock().unwrap().is_some();
+                let window_ready = window.lock().unwrap().is_some();
+                let audio_ready = audio.lock().unwrap().is_some();
+
+                if renderer_ready && game_ready && window_ready && audio_ready {
+                    // Take the values out
+                    let renderer = renderer.lock().unwrap().take().unwrap();
+                    let game = game.lock().unwrap().take().unwrap();
+                    let window = window.lock().unwrap().take().unwrap();
+                    let audio = audio.lock().unwrap().take().unwrap();
+
+                    // Replace self with the new state
+                    *self = AppState::Loaded {
+                        game,
+                        renderer,
+                        window,
+                        input: InputSystem::default(),
+                        audio: audio,
+                    };
+                    true
+                } else {
+                    false
+                }
+            }
+            AppState::Failed | AppState::Loaded { .. } => false,
+        }
+    }
+}
+
+// Replaces the loading status with a message the user can act on
+fn show_load_error(error: &RendererInitError, config: &EngineConfig) {
+    log::error!("Failed to start the renderer: {}", error);
+    let message = match error {
+        RendererInitError::RequestAdapter(_) => {
+            "WebEngine needs WebGL2, which this browser doesn't support or has disabled."
+                .to_string()
+        }
+        other => format!("WebEngine couldn't start: {}.", other),
+    };
+    if let Some(status) = config.status_element() {
+        status.set_text_content(Some(&message));
+    }
+}
+
+// Physics always advances in steps of this size, regardless of framerate
+const FIXED_TIMESTEP: f32 = 1.0 / 120.0;
+// Caps the accumulated backlog so a long stall doesn't trigger a spiral of death
+const MAX_ACCUMULATED_TIME: f32 = 0.25;
+// Frames this close to their slot under a target FPS still count as on time,
+// animation frame timestamps jitter by about this much
+const FRAME_CAP_SLACK_MS: f64 = 1.0;
+
+/// Per-frame timing, measured once per redraw before the fixed steps run.
+pub struct FrameTimer {
+    // Longest frame passed on to the simulation, see set_max_delta_time
+    max_delta_time: f32,
+    raw_delta_time: f32,
+    delta_time: f32,
+    smoothed_fps: f32,
+}
+
+impl FrameTimer {
+    pub const DEFAULT_MAX_DELTA_TIME: f32 = 0.1;
+    // Weight of the newest frame in the FPS moving average
+    const FPS_SMOOTHING: f32 = 0.1;
+
+    /// Frames longer than this (a GC pause, a stall) are clamped so they only
+    /// advance the simulation by `max_delta_time` seconds.
+    pub fn set_max_delta_time(&mut self, max_delta_time: f32) {
+        self.max_delta_time = max_delta_time.max(0.0);
+    }
+
+    pub fn max_delta_time(&self) -> f32 {
+        self.max_delta_time
+    }
+
+    /// The last frame's duration in seconds, as measured.
+    pub fn raw_delta_time(&self) -> f32 {
+        self.raw_delta_time
+    }
+
+    /// The last frame's duration in seconds, clamped to `max_delta_time`.
+    pub fn delta_time(&self) -> f32 {
+        self.delta_time
+    }
+
+    /// Frames per second, smoothed over roughly the last ten frames.
+    pub fn fps(&self) -> f32 {
+        self.smoothed_fps
+    }
+
+    // Records a frame and returns the clamped delta
+    fn tick(&mut self, raw_delta_time: f32) -> f32 {
+        self.raw_delta_time = raw_delta_time;
+        self.delta_time = raw_delta_time.min(self.max_delta_time);
+        if raw_delta_time > 0.0 {
+            let fps = 1.0 / raw_delta_time;
+            self.smoothed_fps = if self.smoothed_fps == 0.0 {
+                fps // First frame, nothing to smooth against yet
+            } else {
+                self.smoothed_fps + (fps - self.smoothed_fps) * Self::FPS_SMOOTHING
+            };
+        }
+        self.delta_time
+    }
+}
+
+impl Default for FrameTimer {
+    fn default() -> Self {
+        Self {
+            max_delta_time: Self::DEFAULT_MAX_DELTA_TIME,
+            raw_delta_time: 0.0,
+            delta_time: 0.0,
+            smoothed_fps: 0.0,
+        }
+    }
+}
+
+struct WebApp {
+    config: EngineConfig,
+    state: Box<AppState>,
+    last_time: Option<f64>,
+    accumulator: f32,
+    frame_timer: FrameTimer,
+    // Set while the tab is unfocused, game updates are skipped
+    paused: bool,
+    pointer_lock_on_click: bool,
+    custom_cursor: bool,
+    target_fps: Option<f32>,
+    // When the next frame is due under target_fps, in performance.now() milliseconds
+    next_frame_at: f64,
+    // Set by the fullscreenchange listener, the canvas is resized on the next frame
+    fullscreen_changed: Rc<Cell<bool>>,
+    fullscreen_listener: Option<Closure<dyn FnMut()>>,
+    // Frames presented so far, passed to the frame callback
+    frame_count: u64,
+}
+
+impl WebApp {
+    fn new(config: EngineConfig) -> Self {
+        let mut app = Self {
+            config,
+            state: Box::new(AppState::Loading {
+                game: Arc::new(Mutex::new(None)),
+                renderer: Arc::new(Mutex::new(None)),
+                window: Arc::new(Mutex::new(None)),
+                audio: Arc::new(Mutex::new(None)),
+                error: Arc::new(Mutex::new(None)),
+            }),
+            last_time: None,
+            accumulator: 0.0,
+            frame_timer: FrameTimer::default(),
+            paused: false,
+            pointer_lock_on_click: false,
+            custom_cursor: false,
+            target_fps: None,
+            next_frame_at: 0.0,
+            fullscreen_changed: Rc::new(Cell::new(false)),
+            fullscreen_listener: None,
+            frame_count: 0,
+        };
+        app.set_target_fps(app.config.target_fps);
+        if let Some(max_delta_time) = app.config.max_delta_time {
+            app.frame_timer.set_max_delta_time(max_delta_time);
+        }
+        app.pointer_lock_on_click = app.config.pointer_lock_on_click;
+        app.set_custom_cursor(app.config.custom_cursor);
+        app
+    }
+
+    /// Caps how often the game updates and draws, below the display's own
+    /// refresh rate if that's higher, by skipping animation frames that come
+    /// too soon. `None`, the default, draws on every frame the browser gives.
+    pub fn set_target_fps(&mut self, target_fps: Option<f32>) {
+        self.target_fps = target_fps.filter(|fps| *fps > 0.0);
+    }
+
+    pub fn target_fps(&self) -> Option<f32> {
+        self.target_fps
+    }
+
+    /// Locks the pointer to the canvas when it is clicked, so the game gets
+    /// raw motion through `InputSystem::mouse_delta` and the cursor is hidden.
+    /// Escape releases the lock. Browsers only grant it from a click, which is
+    /// why it can't be requested right away.
+    pub fn set_pointer_lock_on_click(&mut self, enabled: bool) {
+        self.pointer_lock_on_click = enabled;
+        if !enabled {
+            Self::exit_pointer_lock();
+        }
+    }
+
+    pub fn pointer_lock_on_click(&self) -> bool {
+        self.pointer_lock_on_click
+    }
+
+    /// Hides the OS cursor over the canvas so the game can draw its own at
+    /// the mouse position. The default cursor comes back while the tab is
+    /// unfocused, and when this is disabled.
+    pub fn set_custom_cursor(&mut self, enabled: bool) {
+        self.custom_cursor = enabled;
+    }
+
+    pub fn custom_cursor(&self) -> bool {
+        self.custom_cursor
+    }
+
+    fn set_cursor_hidden(window: &WinitWindow, hidden: bool) {
+        let Some(canvas) = window.canvas() else {
+            return;
+        };
+        let style = canvas.style();
+        let result = if hidden {
+            style.set_property("cursor", "none")
+        } else {
+            style.remove_property("cursor").map(|_| ())
+        };
+        if let Err(err) = result {
+            log::error!("Failed to change the cursor: {:?}", err);
+        }
+    }
+
+    // Toggles fullscreen. Browsers only grant fullscreen from a user gesture,
+    // so it can't be entered from game code. Escape leaves it, which the
+    // browser handles.
+    const FULLSCREEN_KEY: KeyCode = KeyCode::KeyF;
+
+    fn toggle_fullscreen(window: &WinitWindow) {
+        let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+            return;
+        };
+        if Self::is_canvas_fullscreen(window) {
+            document.exit_fullscreen();
+        } else if let Some(canvas) = window.canvas() {
+            if let Err(err) = canvas.request_fullscreen() {
+                log::error!("Failed to enter fullscreen: {:?}", err);
+            }
+        }
+    }
+
+    fn is_canvas_fullscreen(window: &WinitWindow) -> bool {
+        let fullscreen = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.fullscreen_element());
+        match (fullscreen, window.canvas()) {
+            (Some(fullscreen), Some(canvas)) => fullscreen == **canvas,
+            _ => false,
+        }
+    }
+
+    // CSS decides how big the canvas looks; its backing store gets one
+    // texel per device pixel of that, so HiDPI screens stay crisp. Returns
+    // the new size, or None when the canvas isn't laid out.
+    fn fit_canvas_to_css(canvas: &HtmlCanvasElement) -> Option<PhysicalSize<u32>> {
+        let scale_factor = web_sys::window()?.device_pixel_ratio();
+        let (css_width, css_height) = (canvas.client_width(), canvas.client_height());
+        if css_width <= 0 || css_height <= 0 {
+            return None;
+        }
+        let size = PhysicalSize::new(
+            (css_width as f64 * scale_factor).round() as u32,
+            (css_height as f64 * scale_factor).round() as u32,
+        );
+        canvas.set_width(size.width);
+        canvas.set_height(size.height);
+        Some(size)
+    }
+
+    fn exit_pointer_lock() {
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            document.exit_pointer_lock();
+        }
+    }
+
+    // The browser can drop the lock on its own, so ask it instead of tracking it
+    fn is_canvas_locked(window: &WinitWindow) -> bool {
+        let locked = web_sys::window()
+            .and_then(|window| window.document())
+            .and_then(|document| document.pointer_lock_element());
+        match (locked, window.canvas()) {
+            (Some(locked), Some(canvas)) => locked == **canvas,
+            _ => false,
+        }
+    }
+}
+
+impl ApplicationHandler for WebApp {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let window = std::sync::Arc::new(
+            event_loop
+                .create_window(
+                    winit::window::WindowAttributes::default().with_title(&self.config.title),
+                )
+                .unwrap(),
+        );
+
+        let web_window = web_sys::window().unwrap();
+        let document = web_window.document().unwrap();
+        let canvas: HtmlCanvasElement = window.canvas().unwrap();
+
+        let container = document
+            .get_element_by_id(&self.config.container_id)
+            .unwrap_or_else(|| {
+                let body = document.body().unwrap();
+                let container = document.create_element("div").unwrap();
+                container.set_id(&self.config.container_id);
+                body.append_child(&container).unwrap();
+                container
+            });
+
+        container.append_child(&canvas).unwrap();
+
+        if let Some(status_div) = self.config.status_element() {
+            status_div.set_text_content(Some("Loaded!"));
+        }
+
+        let (target_w, target_h) = Game::target_size();
+
+        // winit keeps the backing store in sync on later resizes and pixel
+        // ratio changes, fullscreen is handled on the next frame
+        if Self::fit_canvas_to_css(&canvas).is_none() {
+            // Not laid out yet
+            let scale_factor = web_window.device_pixel_ratio();
+            canvas.set_width((target_w as f64 * scale_factor).round() as u32);
+            canvas.set_height((target_h as f64 * scale_factor).round() as u32);
+        }
+
+        let fullscreen_changed = self.fullscreen_changed.clone();
+        let listener_window = window.clone();
+        let listener = Closure::<dyn FnMut()>::new(move || {
+            fullscreen_changed.set(true);
+            listener_window.request_redraw();
+        });
+        if let Err(err) = document
+            .add_event_listener_with_callback("fullscreenchange", listener.as_ref().unchecked_ref())
+        {
+            log::error!("Failed to listen for fullscreen changes: {:?}", err);
+        }
+        self.fullscreen_listener = Some(listener);
+
+        if let AppState::Loading {
+            game,
+            renderer,
+            window: window_state,
+            audio,
+            error,
+        } = &mut *self.state
+        {
+            // Store the window in the state
+            *window_state.lock().unwrap() = Some(window.clone());
+
+            let renderer_clone = Arc::clone(renderer);
+            let game_clone = Arc::clone(game);
+            let audio_clone = Arc::clone(audio);
+            let error_clone = Arc::clone(error);
+            wasm_bindgen_futures::spawn_local(async move {
+                let mut renderer =
+                    match RenderingSystem::new(window.clone(), target_w, target_h).await {
+                        Ok(renderer) => renderer,
+                        Err(err) => {
+                            *error_clone.lock().unwrap() = Some(err);
+                            // Wake the event loop so the error gets shown
+                            window.request_redraw();
+                            return;
+                        }
+                    };
+                let mut audio_system = AudioSystem::new();
+                let game = Game::init(&mut renderer, &mut audio_system, GameConfig::default());
+
+                *renderer_clone.lock().unwrap() = Some(renderer);
+                *game_clone.lock().unwrap() = Some(game);
+                *audio_clone.lock().unwrap() = Some(audio_system);
+            });
+        } else {
+            panic!("AppState is not Loading");
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        _window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        // Try to advance the state
+        self.state.advance_in_place(&self.config);
+
+        // Handle events if we're loaded
+        if let AppState::Loaded {
+            game,
+            renderer,
+            window,
+            input,
+            audio,
+        } = &mut *self.state
+        {
+            match event {
+                WindowEvent::CloseRequested => {
+                    event_loop.exit();
+                }
+                WindowEvent::Resized(physical_size) => {
+                    // Handle resize - you'll need to implement this method on your renderer
+                    // renderer.resize(physical_size.width, physical_size.height);
+                    renderer.resize(physical_size);
+                }
+                WindowEvent::RedrawRequested => {
+                    // Handle render - you'll need to implement this method
+                    // match renderer.render(&game) {
+                    //     Ok(_) => {}
+                    //     Err(e) => log::error!("Render error: {:?}", e),
+                    // }
+                    let now = web_sys::window().unwrap().performance().unwrap().now();
+                    if let Some(target_fps) = self.target_fps {
+                        if now < self.next_frame_at - FRAME_CAP_SLACK_MS {
+                            // Too soon, try again on the next animation frame
+                            window.request_redraw();
+                            return;
+                        }
+                        // Keep to the schedule, so 60 on a 144Hz display
+                        // averages 60 rather than every third frame, unless
+                        // a stall left it more than a frame behind
+                        self.next_frame_at += 1000.0 / target_fps as f64;
+                        if self.next_frame_at < now {
+                            self.next_frame_at = now + 1000.0 / target_fps as f64;
+                        }
+                    }
+
+                    // Entering and leaving fullscreen changes the canvas' CSS
+                    // size, the surface follows it like any other resize
+                    if self.fullscreen_changed.replace(false) {
+                        if let Some(canvas) = window.canvas() {
+                            if let Some(size) = Self::fit_canvas_to_css(&canvas) {
+                                renderer.resize(size);
+                            }
+                        }
+                    }
+                    input.content_rect = renderer.content_rect();
+                    input.pointer_locked = Self::is_canvas_locked(window);
+                    let custom_cursor = self.custom_cursor && !self.paused;
+                    if input.custom_cursor != custom_cursor {
+                        input.custom_cursor = custom_cursor;
+                        Self::set_cursor_hidden(window, custom_cursor);
+                    }
+                    // Only call update if we have a last time
+                    if let (Some(last_time), false) = (self.last_time, self.paused) {
+                        let raw_delta_time = (now - last_time) as f32 / 1000.0; // Convert to seconds
+                        let delta_time = self.frame_timer.tick(raw_delta_time);
+                        FPS.set(self.frame_timer.fps());
+                        self.accumulator =
+                            (self.accumulator + delta_time).min(MAX_ACCUMULATED_TIME);
+                        REPLAY.with_borrow_mut(|replay| replay.start_requested(game));
+                        while self.accumulator >= FIXED_TIMESTEP {
+                            REPLAY.with_borrow_mut(|replay| replay.step(game, input, audio));
+                            // Edges are only reported to the first step of a frame
+                            input.end_frame();
+                            self.accumulator -= FIXED_TIMESTEP;
+                        }
+                    }
+                    self.last_time = Some(now);
+
+                    renderer.set_post_effect(game.post_effect());
+                    match renderer.render(game) {
+                        Ok(_) if !renderer.is_visible() => {}
+                        Ok(_) => {
+                            notify_frame_complete(
+                                self.frame_count,
+                                self.frame_timer.raw_delta_time(),
+                            );
+                            self.frame_count += 1;
+                        }
+                        // Browsers report an outdated surface when the pixel ratio changes
+                        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                            renderer.canonical_resize();
+                        }
+                        Err(wgpu::SurfaceError::OutOfMemory) => event_loop.exit(),
+                        // Nothing to fix, the next frame tries again
+                        Err(wgpu::SurfaceError::Timeout) => {}
+                        Err(e) => log::error!("{:?}", e),
+                    }
+
+                    window.request_redraw();
+                }
+                WindowEvent::Focused(focused) => {
+                    self.paused = !focused;
+                    audio.set_paused(!focused);
+                    if !focused && input.custom_cursor {
+                        // Give the cursor back right away, the next frame may be a while
+                        input.custom_cursor = false;
+                        Self::set_cursor_hidden(window, false);
+                    }
+                    if focused {
+                        // Don't count the time spent unfocused as a frame
+                        self.last_time = None;
+                        self.accumulator = 0.0;
+                    }
+                }
+                WindowEvent::MouseInput { button, state, .. } => {
+                    // Update mouse input state
+                    input.record(InputEvent::Mouse(button, state));
+                    audio.on_user_interaction();
+                    // Still inside the click handler, which the browser requires
+                    if self.pointer_lock_on_click
+                        && state == ElementState::Pressed
+                        && !Self::is_canvas_locked(window)
+                    {
+                        if let Some(canvas) = window.canvas() {
+                            canvas.request_pointer_lock();
+                        }
+                    }
+                }
+                WindowEvent::Touch(touch) => {
+                    input.update_touch(&touch);
+                    // Mobile autoplay policies need a user gesture before audio can start
+                    if touch.phase == TouchPhase::Started {
+                        audio.on_user_interaction();
+                    }
+                }
+                WindowEvent::MouseWheel { delta, .. } => {
+                    input.add_scroll(delta);
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    // Update mouse position
+                    input.mouse_position = (position.x, position.y);
+                }
+                WindowEvent::KeyboardInput { event, .. } => {
+                    // Handle keyboard input if needed
+                    let KeyEvent {
+                        physical_key,
+                        logical_key,
+                        state,
+                        repeat,
+                        ..
+                    } = event;
+                    // OS key repeat must not look like a fresh press
+                    if !repeat {
+                        if let PhysicalKey::Code(code) = physical_key {
+                            input.record(InputEvent::PhysicalKey(code, state));
+                        }
+                        input.record(InputEvent::LogicalKey(
+                            InputSystem::normalize_logical_key(logical_key),
+                            state,
+                        ));
+                    }
+                    // Browsers release on Escape themselves, but not all of them
+                    // pass the key on, so this covers the ones that do
+                    if physical_key == PhysicalKey::Code(KeyCode::Escape)
+                        && state == ElementState::Pressed
+                        && input.pointer_locked
+                    {
+                        Self::exit_pointer_lock();
+                        input.pointer_locked = false;
+                    }
+                    // Still inside the key handler, which the browser requires
+                    if physical_key == PhysicalKey::Code(Self::FULLSCREEN_KEY)
+                        && state == ElementState::Pressed
+                        && !repeat
+                    {
+                        Self::toggle_fullscreen(window);
+                    }
+                    audio.on_user_interaction();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        let AppState::Loaded { input, .. } = &mut *self.state else {
+            return;
+        };
+        // Unlocked motion is already covered by CursorMoved
+        if let DeviceEvent::MouseMotion { delta } = event {
+            if input.pointer_locked {
+                input.mouse_delta.0 += delta.0;
+                input.mouse_delta.1 += delta.1;
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use glam::{UVec2, Vec2};
+
+#[cfg(target_arch = "wasm32")]
+use crate::renderer::TextureHandle;
+
+/// A texture split into a grid of equally sized frames, such as a sprite sheet.
+/// Frames are numbered row by row starting from the top left.
+pub struct TextureAtlas {
+    // Only the uv math builds outside the browser
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) texture: TextureHandle,
+    grid: UVec2,
+}
+
+impl TextureAtlas {
+    /// `grid` is the number of columns and rows in the texture.
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(texture: TextureHandle, grid: UVec2) -> Self {
+        Self {
+            texture,
+            grid: grid.max(UVec2::ONE),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn texture(&self) -> &TextureHandle {
+        &self.texture
+    }
+
+    pub fn grid(&self) -> UVec2 {
+        self.grid
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.grid.x * self.grid.y
+    }
+
+    /// The `(uv_min, uv_max)` rect of the cell at `column`, `row` in a texture
+    /// divided into `grid` cells, ready for `Drawer::draw_textured_square_uv`.
+    pub fn frame(column: u32, row: u32, grid: UVec2) -> (Vec2, Vec2) {
+        let grid = grid.max(UVec2::ONE).as_vec2();
+        let min = Vec2::new(column as f32, row as f32) / grid;
+        let max = Vec2::new((column + 1) as f32, (row + 1) as f32) / grid;
+        (min, max)
+    }
+
+    /// Like `frame`, but picks the cell by its number, wrapping around past
+    /// the last one.
+    pub fn frame_at(index: u32, grid: UVec2) -> (Vec2, Vec2) {
+        let grid = grid.max(UVec2::ONE);
+        let index = index % (grid.x * grid.y);
+        Self::frame(index % grid.x, index / grid.x, grid)
+    }
+
+    /// The uv rect of frame `index`, wrapping around so an animation can just
+    /// keep counting up.
+    pub fn frame_uv(&self, index: u32) -> (Vec2, Vec2) {
+        Self::frame_at(index, self.grid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRID: UVec2 = UVec2::new(4, 4);
+
+    #[test]
+    fn frames_of_a_4x4_atlas_are_quarter_cells() {
+        assert_eq!(
+            TextureAtlas::frame(0, 0, GRID),
+            (Vec2::ZERO, Vec2::splat(0.25))
+        );
+        assert_eq!(
+            TextureAtlas::frame(2, 1, GRID),
+            (Vec2::new(0.5, 0.25), Vec2::new(0.75, 0.5))
+        );
+        assert_eq!(
+            TextureAtlas::frame(3, 3, GRID),
+            (Vec2::splat(0.75), Vec2::ONE)
+        );
+    }
+
+    #[test]
+    fn frame_numbers_go_row_by_row_and_wrap() {
+        assert_eq!(
+            TextureAtlas::frame_at(1, GRID),
+            TextureAtlas::frame(1, 0, GRID)
+        );
+        assert_eq!(
+            TextureAtlas::frame_at(6, GRID),
+            TextureAtlas::frame(2, 1, GRID)
+        );
+        assert_eq!(
+            TextureAtlas::frame_at(15, GRID),
+            TextureAtlas::frame(3, 3, GRID)
+        );
+        // Past the last frame the animation starts over
+        assert_eq!(
+            TextureAtlas::frame_at(16, GRID),
+            TextureAtlas::frame(0, 0, GRID)
+        );
+        assert_eq!(
+            TextureAtlas::frame_at(22, GRID),
+            TextureAtlas::frame(2, 1, GRID)
+        );
+    }
+}
@@ -1,16 +1,18 @@
 use std::{
     cell::RefCell,
+    collections::HashMap,
     future::IntoFuture,
     rc::Rc,
     sync::{Arc, Mutex},
 };
 
 use log::error;
-use wasm_bindgen::JsCast;
+use wasm_bindgen::{closure::Closure, JsCast};
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
     js_sys::{ArrayBuffer, Uint8Array},
-    AudioBuffer, AudioContext, AudioContextState,
+    AudioBuffer, AudioBufferSourceNode, AudioContext, AudioContextState, AudioNode,
+    AudioScheduledSourceNode, GainNode,
 };
 
 enum LoadState {
@@ -22,23 +24,189 @@ enum LoadState {
 enum LoadableAudio {
     Loading(Rc<RefCell<LoadState>>),
     Loaded(AudioBuffer),
+    // Decoding failed, playing it does nothing
+    Failed,
+    Dummy,
+}
+
+/// Where a sound is in its loading, see `AudioSystem::state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioState {
+    /// Still decoding, playing it does nothing yet.
+    Loading,
+    Ready,
+    /// The data couldn't be decoded.
+    Failed,
+    /// There's no audio context, so nothing will ever play.
     Dummy,
 }
 
 pub struct AudioSystem {
     audio_context: Option<AudioContext>,
+    // Every voice is routed through this node, so it also affects sounds that are already playing
+    master_gain: Option<GainNode>,
     audio_buffers: Vec<LoadableAudio>,
+    // Voices that haven't ended yet. Shared with their `onended` callbacks, which remove them
+    voices: Rc<RefCell<HashMap<PlaybackId, Voice>>>,
+    next_playback_id: u64,
+    // Per handle, see set_max_voices
+    max_voices: usize,
+    // When each handle last started a voice, in performance.now() milliseconds
+    last_played: HashMap<usize, f64>,
 }
 
 pub struct AudioHandle {
     index: usize,
 }
 
+/// A stretch of a sound, in seconds from its start, see `SoundSprite`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundRegion {
+    pub start_secs: f32,
+    pub duration_secs: f32,
+}
+
+/// Several short sounds packed one after another into one file, so they
+/// are decoded once. Each gets a name and its region of the file, and plays
+/// with `AudioSystem::play_sprite`.
+pub struct SoundSprite {
+    handle: AudioHandle,
+    regions: HashMap<String, SoundRegion>,
+}
+
+impl SoundSprite {
+    pub fn new(handle: AudioHandle) -> Self {
+        Self {
+            handle,
+            regions: HashMap::new(),
+        }
+    }
+
+    /// Names a region, replacing any region that already had the name. It's
+    /// checked against the length of the sound when it is played, since that
+    /// isn't known until decoding finishes.
+    pub fn add_region(&mut self, name: &str, start_secs: f32, duration_secs: f32) {
+        self.regions.insert(
+            name.to_owned(),
+            SoundRegion {
+                start_secs,
+                duration_secs,
+            },
+        );
+    }
+
+    pub fn region(&self, name: &str) -> Option<SoundRegion> {
+        self.regions.get(name).copied()
+    }
+
+    pub fn handle(&self) -> &AudioHandle {
+        &self.handle
+    }
+}
+
+/// Identifies a single playing voice. It stays valid until the voice is
+/// stopped or ends naturally.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PlaybackId(u64);
+
+struct Voice {
+    source: AudioBufferSourceNode,
+    // The voice's own gain, for fades
+    gain: GainNode,
+    // Index of the AudioHandle it plays
+    handle: usize,
+}
+
+// Everything that can vary between two plays of the same buffer
+struct VoiceOptions {
+    speed: f32,
+    gain: f32,
+    // -1 is fully left, 1 fully right
+    pan: f32,
+    // Pitch shift in cents, applied on top of speed
+    detune: f32,
+    // Seconds to ramp up from silence, 0 starts at full gain
+    fade_in: f32,
+    looping: bool,
+    // Only this part of the buffer plays, see play_region
+    region: Option<SoundRegion>,
+}
+
+impl Default for VoiceOptions {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            gain: 1.0,
+            pan: 0.0,
+            detune: 0.0,
+            fade_in: 0.0,
+            looping: false,
+            region: None,
+        }
+    }
+}
+
 impl AudioSystem {
+    pub const DEFAULT_MAX_VOICES: usize = 8;
+
     pub fn new() -> Self {
+        let audio_context = AudioContext::new().ok();
+        let master_gain = audio_context.as_ref().and_then(|audio_context| {
+            let gain = audio_context.create_gain().ok()?;
+            gain.connect_with_audio_node(&audio_context.destination())
+                .ok()?;
+            Some(gain)
+        });
         Self {
-            audio_context: AudioContext::new().ok(),
+            audio_context,
+            master_gain,
             audio_buffers: Vec::new(),
+            voices: Rc::new(RefCell::new(HashMap::new())),
+            next_playback_id: 0,
+            max_voices: Self::DEFAULT_MAX_VOICES,
+            last_played: HashMap::new(),
+        }
+    }
+
+    /// Caps how many voices of the same sound can play at once. Playing it
+    /// again past the cap stops its oldest voice first, so quick repeats
+    /// don't pile up and clip.
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.max(1);
+    }
+
+    // Stops the oldest voices of `handle` until another one fits under the cap
+    fn steal_voices(&mut self, handle: usize) {
+        let mut voices = self.voices.borrow_mut();
+        let mut playing: Vec<PlaybackId> = voices
+            .iter()
+            .filter(|(_, voice)| voice.handle == handle)
+            .map(|(id, _)| *id)
+            .collect();
+        if playing.len() < self.max_voices {
+            return;
+        }
+        // Ids are handed out in order, so the smallest is the oldest
+        playing.sort_by_key(|id| id.0);
+        for id in &playing[..=playing.len() - self.max_voices] {
+            if let Some(voice) = voices.remove(id) {
+                let _ = AudioScheduledSourceNode::stop(&voice.source);
+            }
+        }
+    }
+
+    pub fn set_master_volume(&mut self, volume: f32) {
+        if let Some(master_gain) = &self.master_gain {
+            master_gain.gain().set_value(volume.max(0.0));
+        }
+    }
+
+    // Where voices should connect to: the master gain, or the speakers if it couldn't be created
+    fn output_node(&self) -> Option<AudioNode> {
+        match (&self.master_gain, &self.audio_context) {
+            (Some(master_gain), _) => Some(master_gain.clone().into()),
+            (None, Some(audio_context)) => Some(audio_context.destination().into()),
+            (None, None) => None,
         }
     }
 
@@ -50,6 +218,17 @@ impl AudioSystem {
         }
     }
 
+    /// Suspends or resumes the whole audio context, freezing every voice.
+    pub fn set_paused(&mut self, paused: bool) {
+        if let Some(audio_context) = &self.audio_context {
+            if paused {
+                let _ = audio_context.suspend();
+            } else if audio_context.state() == AudioContextState::Suspended {
+                let _ = audio_context.resume();
+            }
+        }
+    }
+
     pub fn load_buffer(&mut self, bytes: &[u8]) -> AudioHandle {
         let handle = AudioHandle {
             index: self.audio_buffers.len(),
@@ -93,15 +272,231 @@ impl AudioSystem {
         handle
     }
 
+    pub fn state(&self, handle: &AudioHandle) -> AudioState {
+        match &self.audio_buffers[handle.index] {
+            LoadableAudio::Loading(state) => match &*state.borrow() {
+                LoadState::Loading => AudioState::Loading,
+                LoadState::Done(_) => AudioState::Ready,
+                LoadState::Failed => AudioState::Failed,
+            },
+            LoadableAudio::Loaded(_) => AudioState::Ready,
+            LoadableAudio::Failed => AudioState::Failed,
+            LoadableAudio::Dummy => AudioState::Dummy,
+        }
+    }
+
+    pub fn is_ready(&self, handle: &AudioHandle) -> bool {
+        self.state(handle) == AudioState::Ready
+    }
+
+    /// True once no sound is still loading. Sounds that failed or can't play
+    /// at all count as settled, so a loading screen waiting on this always
+    /// finishes.
+    pub fn all_ready(&self) -> bool {
+        (0..self.audio_buffers.len())
+            .all(|index| self.state(&AudioHandle { index }) != AudioState::Loading)
+    }
+
     pub fn play(&mut self, handle: &AudioHandle, speed: f32) {
+        self.play_with_gain(handle, speed, 1.0);
+    }
+
+    pub fn play_with_gain(&mut self, handle: &AudioHandle, speed: f32, gain: f32) {
+        self.play_voice(
+            handle,
+            VoiceOptions {
+                speed,
+                gain,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Like `play`, but does nothing if the same sound started less than
+    /// `min_interval_ms` ago, so an event firing on several frames in a row
+    /// doesn't buzz. Returns whether it played.
+    pub fn play_throttled(
+        &mut self,
+        handle: &AudioHandle,
+        speed: f32,
+        min_interval_ms: f64,
+    ) -> bool {
+        if self.played_within(handle, min_interval_ms) {
+            return false;
+        }
+        self.play_voice(
+            handle,
+            VoiceOptions {
+                speed,
+                ..Default::default()
+            },
+        )
+        .is_some()
+    }
+
+    /// Whether any play of `handle` started in the last `interval_ms`, for
+    /// throttling the other `play_*` variants the same way as `play_throttled`.
+    pub fn played_within(&self, handle: &AudioHandle, interval_ms: f64) -> bool {
+        match (self.last_played.get(&handle.index), Self::now_ms()) {
+            (Some(last), Some(now)) => now - last < interval_ms,
+            _ => false,
+        }
+    }
+
+    fn now_ms() -> Option<f64> {
+        web_sys::window()
+            .and_then(|window| window.performance())
+            .map(|performance| performance.now())
+    }
+
+    /// Plays the sound shifted by `detune_cents`, 100 per semitone, which is
+    /// easier to keep in tune than a raw `speed` ratio. Note that browsers
+    /// detune buffer sources by resampling, so like `speed` this still makes
+    /// the sound shorter as it gets higher; there's no pitch-only shift in
+    /// Web Audio without a custom processor.
+    pub fn play_detuned(&mut self, handle: &AudioHandle, detune_cents: f32) {
+        self.play_voice(
+            handle,
+            VoiceOptions {
+                detune: detune_cents,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Plays the sound placed in the stereo field, from -1 (left) to 1 (right).
+    pub fn play_panned(&mut self, handle: &AudioHandle, speed: f32, pan: f32) {
+        self.play_voice(
+            handle,
+            VoiceOptions {
+                speed,
+                pan: pan.clamp(-1.0, 1.0),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Plays the sound ramping up from silence over `fade_in_secs`, which
+    /// avoids the click of a sound starting abruptly. Returns `None` if the
+    /// sound can't be played yet, like `play_looping`.
+    pub fn play_with_fade(
+        &mut self,
+        handle: &AudioHandle,
+        speed: f32,
+        fade_in_secs: f32,
+    ) -> Option<PlaybackId> {
+        self.play_voice(
+            handle,
+            VoiceOptions {
+                speed,
+                fade_in: fade_in_secs.max(0.0),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Ramps a voice down to silence over `secs` and then stops it. It still
+    /// counts as playing until the ramp is over.
+    pub fn fade_out(&mut self, id: PlaybackId, secs: f32) {
+        let Some(audio_context) = &self.audio_context else {
+            return;
+        };
+        let voices = self.voices.borrow();
+        let Some(voice) = voices.get(&id) else {
+            return;
+        };
+        let now = audio_context.current_time();
+        let end = now + secs.max(0.0) as f64;
+        let gain = voice.gain.gain();
+        // Start the ramp from wherever the gain is now, even mid fade-in
+        let _ = gain.cancel_scheduled_values(now);
+        let _ = gain.set_value_at_time(gain.value(), now);
+        let _ = gain.linear_ramp_to_value_at_time(0.0, end);
+        let _ = AudioScheduledSourceNode::stop_with_when(&voice.source, end);
+    }
+
+    /// Plays `duration_secs` of the sound starting `start_secs` in. Returns
+    /// `None` without playing if the region doesn't fit inside the sound, or
+    /// if the sound can't be played yet, like `play_looping`.
+    pub fn play_region(
+        &mut self,
+        handle: &AudioHandle,
+        start_secs: f32,
+        duration_secs: f32,
+        speed: f32,
+    ) -> Option<PlaybackId> {
+        self.play_voice(
+            handle,
+            VoiceOptions {
+                speed,
+                region: Some(SoundRegion {
+                    start_secs,
+                    duration_secs,
+                }),
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Plays the region of `sprite` called `name`, see `play_region`.
+    pub fn play_sprite(
+        &mut self,
+        sprite: &SoundSprite,
+        name: &str,
+        speed: f32,
+    ) -> Option<PlaybackId> {
+        let Some(region) = sprite.region(name) else {
+            log::warn!("Sound sprite has no region called {:?}", name);
+            return None;
+        };
+        self.play_region(
+            &sprite.handle,
+            region.start_secs,
+            region.duration_secs,
+            speed,
+        )
+    }
+
+    /// Plays the sound on a loop until it is stopped. Returns `None` if the
+    /// sound can't be played yet (still loading, failed, or no audio context).
+    pub fn play_looping(&mut self, handle: &AudioHandle, speed: f32) -> Option<PlaybackId> {
+        self.play_voice(
+            handle,
+            VoiceOptions {
+                speed,
+                looping: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn is_playing(&self, id: PlaybackId) -> bool {
+        self.voices.borrow().contains_key(&id)
+    }
+
+    pub fn stop(&mut self, id: PlaybackId) {
+        let voice = self.voices.borrow_mut().remove(&id);
+        if let Some(voice) = voice {
+            let _ = AudioScheduledSourceNode::stop(&voice.source);
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        let voices: Vec<Voice> = self.voices.borrow_mut().drain().map(|(_, v)| v).collect();
+        for voice in voices {
+            let _ = AudioScheduledSourceNode::stop(&voice.source);
+        }
+    }
+
+    fn play_voice(&mut self, handle: &AudioHandle, options: VoiceOptions) -> Option<PlaybackId> {
         // If it's dummy, do nothing
-        // If it's loading and failed, convert to dummy
+        // If it's loading and failed, convert to failed
         // If it's loading and done, convert to loaded and call play again
         // If it's loaded, play the audio
 
         enum QueryResult {
             IntoLoaded,
-            IntoDummy,
+            IntoFailed,
             Noop,
             DoPlay,
         }
@@ -120,12 +515,13 @@ impl AudioSystem {
                     }
                     LoadState::Done(audio_buffer) => QueryResult::IntoLoaded,
                     LoadState::Failed => {
-                        log::error!("Failed to load audio, converting to dummy");
-                        QueryResult::IntoDummy
+                        log::error!("Failed to load audio, it won't play");
+                        QueryResult::IntoFailed
                     }
                 }
             }
             LoadableAudio::Loaded(audio_buffer) => QueryResult::DoPlay,
+            LoadableAudio::Failed => QueryResult::Noop,
         };
         match result {
             QueryResult::IntoLoaded => {
@@ -136,32 +532,112 @@ impl AudioSystem {
                             audio_buffer.clone()
                         } else {
                             log::error!("Expected audio to be loaded, but it was not");
-                            return;
+                            return None;
                         }
                     }
                     _ => unreachable!(),
                 };
                 self.audio_buffers[handle.index] = LoadableAudio::Loaded(audio_buffer);
-                self.play(handle, speed); // Call play again with the loaded audio
+                self.play_voice(handle, options) // Call play again with the loaded audio
             }
-            QueryResult::IntoDummy => {
-                self.audio_buffers[handle.index] = LoadableAudio::Dummy;
+            QueryResult::IntoFailed => {
+                self.audio_buffers[handle.index] = LoadableAudio::Failed;
+                None
             }
-            QueryResult::Noop => {}
+            QueryResult::Noop => None,
             QueryResult::DoPlay => {
-                if let LoadableAudio::Loaded(audio_buffer) = &self.audio_buffers[handle.index] {
-                    if let Some(audio_context) = &self.audio_context {
-                        let source = audio_context.create_buffer_source().unwrap();
-                        source.set_buffer(Some(audio_buffer));
-                        source.playback_rate().set_value(speed); // Set playback speed
-                        source
-                            .connect_with_audio_node(&audio_context.destination())
-                            .unwrap();
-                        source.start().unwrap();
-                    } else {
-                        log::error!("Audio context is not initialized");
+                if let Some(region) = options.region {
+                    let LoadableAudio::Loaded(audio_buffer) = &self.audio_buffers[handle.index]
+                    else {
+                        return None;
+                    };
+                    let length = audio_buffer.duration();
+                    let start = region.start_secs as f64;
+                    let end = start + region.duration_secs as f64;
+                    // A little slack, regions are usually written down rounded
+                    if start < 0.0 || region.duration_secs <= 0.0 || end > length + 1e-3 {
+                        log::error!(
+                            "Sound region {:?} doesn't fit in a sound of {} seconds",
+                            region,
+                            length
+                        );
+                        return None;
+                    }
+                }
+                self.steal_voices(handle.index);
+                let LoadableAudio::Loaded(audio_buffer) = &self.audio_buffers[handle.index] else {
+                    return None;
+                };
+                let (Some(audio_context), Some(output)) = (&self.audio_context, self.output_node())
+                else {
+                    log::error!("Audio context is not initialized");
+                    return None;
+                };
+
+                let source = audio_context.create_buffer_source().unwrap();
+                source.set_buffer(Some(audio_buffer));
+                source.playback_rate().set_value(options.speed); // Set playback speed
+                source.detune().set_value(options.detune);
+                source.set_loop(options.looping);
+                let voice_gain = audio_context.create_gain().unwrap();
+                let gain = options.gain.max(0.0);
+                if options.fade_in > 0.0 {
+                    let now = audio_context.current_time();
+                    let _ = voice_gain.gain().set_value_at_time(0.0, now);
+                    let _ = voice_gain
+                        .gain()
+                        .linear_ramp_to_value_at_time(gain, now + options.fade_in as f64);
+                } else {
+                    voice_gain.gain().set_value(gain);
+                }
+                source.connect_with_audio_node(&voice_gain).unwrap();
+                // Centered voices skip the panner, as do browsers without one
+                let panner = (options.pan != 0.0)
+                    .then(|| audio_context.create_stereo_panner().ok())
+                    .flatten();
+                match &panner {
+                    Some(panner) => {
+                        panner.pan().set_value(options.pan);
+                        voice_gain.connect_with_audio_node(panner).unwrap();
+                        panner.connect_with_audio_node(&output).unwrap();
                     }
+                    None => {
+                        voice_gain.connect_with_audio_node(&output).unwrap();
+                    }
+                }
+
+                let id = PlaybackId(self.next_playback_id);
+                self.next_playback_id += 1;
+
+                // Forget the voice once it finishes (or is stopped)
+                let voices = Rc::clone(&self.voices);
+                let on_ended = Closure::once_into_js(move || {
+                    voices.borrow_mut().remove(&id);
+                });
+                AudioScheduledSourceNode::set_onended(&source, Some(on_ended.unchecked_ref()));
+
+                match options.region {
+                    Some(region) => source
+                        .start_with_when_and_grain_offset_and_grain_duration(
+                            0.0,
+                            region.start_secs as f64,
+                            region.duration_secs as f64,
+                        )
+                        .unwrap(),
+                    None => source.start().unwrap(),
+                }
+                if let Some(now) = Self::now_ms() {
+                    self.last_played.insert(handle.index, now);
                 }
+                self.voices.borrow_mut().insert(
+                    id,
+                    Voice {
+                        source,
+                        gain: voice_gain,
+                        handle: handle.index,
+                    },
+                );
+                Some(id)
             }
         }
     }
@@ -1,8 +1,15 @@
-use glam::Vec3;
-use log::info;
+use std::collections::{BTreeSet, HashMap};
+
+use glam::{Vec2, Vec3};
 
 use crate::geometry::Transform;
 
+#[cfg(test)]
+thread_local! {
+    // Pairs that got past the bounding boxes, see Collision::full_test
+    static FULL_TESTS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
 #[derive(Debug, Clone)]
 pub struct Collision {
     // Vertices of space A that are inside space B
@@ -22,6 +29,20 @@ pub struct Collision {
 
     // Intersection points for physics calculations
     pub intersection_points: Vec<Vec3>,
+
+    // World-space corners of space A and space B, used to resolve the overlap
+    pub my_corners: [Vec3; 4],
+    pub other_corners: [Vec3; 4],
+}
+
+/// Where a ray first meets a quad, see `Collision::raycast`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    // From the ray origin, in world units
+    pub distance: f32,
+    pub point: Vec2,
+    // Unit length, facing back towards the ray
+    pub normal: Vec2,
 }
 
 #[derive(Debug, Clone)]
@@ -98,9 +119,22 @@ impl Collision {
             other_space_inside_me: false,
             i_am_inside_other: false,
             intersection_points: Vec::new(),
+            my_corners: [Vec3::ZERO; 4],
+            other_corners: [Vec3::ZERO; 4],
         }
     }
 
+    /// The average of the points where the edges of the two spaces cross, a
+    /// good single contact point for effects. `None` if no edges cross, such
+    /// as when one space is entirely inside the other.
+    pub fn contact_centroid(&self) -> Option<Vec3> {
+        if self.intersection_points.is_empty() {
+            return None;
+        }
+        let sum: Vec3 = self.intersection_points.iter().sum();
+        Some(sum / self.intersection_points.len() as f32)
+    }
+
     pub fn has_collision(&self) -> bool {
         self.my_vertices_inside.any()
             || self.other_vertices_inside.any()
@@ -109,7 +143,68 @@ impl Collision {
             || self.i_am_inside_other
     }
 
+    /// Computes the minimum translation vector that separates space A from
+    /// space B, as a unit normal and a penetration depth. Moving A by
+    /// `normal * depth` resolves the overlap.
+    ///
+    /// The spaces are compared through their world-space bounding boxes, so
+    /// for axis-aligned boxes this is the push-out along the shallowest axis.
+    pub fn resolution(&self) -> Option<(Vec3, f32)> {
+        if !self.has_collision() {
+            return None;
+        }
+
+        let (my_min, my_max) = Self::corners_aabb(&self.my_corners);
+        let (other_min, other_max) = Self::corners_aabb(&self.other_corners);
+
+        let overlap = my_max.min(other_max) - my_min.max(other_min);
+        if overlap.x < 0.0 || overlap.y < 0.0 {
+            return None;
+        }
+
+        let my_center = (my_min + my_max) * 0.5;
+        let other_center = (other_min + other_max) * 0.5;
+
+        if overlap.x < overlap.y {
+            let sign = if my_center.x < other_center.x {
+                -1.0
+            } else {
+                1.0
+            };
+            Some((Vec3::new(sign, 0.0, 0.0), overlap.x))
+        } else {
+            let sign = if my_center.y < other_center.y {
+                -1.0
+            } else {
+                1.0
+            };
+            Some((Vec3::new(0.0, sign, 0.0), overlap.y))
+        }
+    }
+
+    /// Whether the world-space bounding boxes of the two spaces overlap or
+    /// touch, in x and y. Cheap next to `do_spaces_collide`, which needs two
+    /// matrix inverses, and never false when that finds a collision.
+    pub fn aabb_overlap(a: &Transform, b: &Transform) -> bool {
+        let (a_min, a_max) = Self::corners_aabb(&Self::get_world_corners(a));
+        let (b_min, b_max) = Self::corners_aabb(&Self::get_world_corners(b));
+        a_min.x <= b_max.x && b_min.x <= a_max.x && a_min.y <= b_max.y && b_min.y <= a_max.y
+    }
+
     pub fn do_spaces_collide(a: &Transform, b: &Transform) -> Option<Collision> {
+        // Most pairs are far apart, skip the inverses for those
+        if !Self::aabb_overlap(a, b) {
+            return None;
+        }
+        Self::full_test(a, b)
+    }
+
+    // Everything do_spaces_collide checks past the bounding boxes, four
+    // matrix inverses in all
+    fn full_test(a: &Transform, b: &Transform) -> Option<Collision> {
+        #[cfg(test)]
+        FULL_TESTS.set(FULL_TESTS.get() + 1);
+
         let mut collision_info = Collision::new();
 
         // Check vertices of A inside B
@@ -129,6 +224,9 @@ impl Collision {
         collision_info.intersection_points =
             Self::collect_intersection_points(&collision_info.my_edge_intersections);
 
+        collision_info.my_corners = Self::get_world_corners(a);
+        collision_info.other_corners = Self::get_world_corners(b);
+
         if collision_info.has_collision() {
             Some(collision_info)
         } else {
@@ -136,6 +234,233 @@ impl Collision {
         }
     }
 
+    /// Sweeps `moving` along the segment from `from` to `to` and returns the
+    /// time of impact in [0, 1] against `target`, if any.
+    ///
+    /// `moving` must be the space placed at `from`, and `from`/`to` are in the
+    /// same coordinate space the transforms project into. Both quads are
+    /// treated as their world-space AABBs. A space that is touching the target
+    /// but moving away from it does not count as a hit.
+    pub fn sweep_space(
+        moving: &Transform,
+        from: Vec2,
+        to: Vec2,
+        target: &Transform,
+    ) -> Option<f32> {
+        let (moving_min, moving_max) = Self::get_world_aabb(moving);
+        let (target_min, target_max) = Self::get_world_aabb(target);
+
+        // Shrink the moving box to a point and grow the target by its extents
+        let half_extents = (moving_max - moving_min).truncate() * 0.5;
+        let start = (moving_min + moving_max).truncate() * 0.5;
+        let expanded_min = target_min.truncate() - half_extents;
+        let expanded_max = target_max.truncate() + half_extents;
+        let displacement = to - from;
+
+        let mut t_entry = f32::NEG_INFINITY;
+        let mut t_exit = f32::INFINITY;
+
+        for axis in 0..2 {
+            let (origin, delta) = (start[axis], displacement[axis]);
+            let (min, max) = (expanded_min[axis], expanded_max[axis]);
+
+            if delta.abs() < f32::EPSILON {
+                if origin < min || origin > max {
+                    return None; // Parallel to this slab and outside it
+                }
+            } else {
+                let t1 = (min - origin) / delta;
+                let t2 = (max - origin) / delta;
+                t_entry = t_entry.max(t1.min(t2));
+                t_exit = t_exit.min(t1.max(t2));
+            }
+        }
+
+        if t_entry > t_exit || t_exit <= 0.0 || t_entry > 1.0 {
+            None
+        } else {
+            Some(t_entry.max(0.0))
+        }
+    }
+
+    /// Separating axis test between the two transformed quads, which may be
+    /// arbitrarily rotated. Returns the axis of least penetration, oriented so
+    /// that moving A by `axis * depth` separates it from B, or `None` if a
+    /// separating axis exists.
+    pub fn sat_overlap(a: &Transform, b: &Transform) -> Option<(Vec3, f32)> {
+        let a_corners = Self::get_world_corners(a).map(|c| c.truncate());
+        let b_corners = Self::get_world_corners(b).map(|c| c.truncate());
+
+        let mut best: Option<(Vec2, f32)> = None;
+
+        for corners in [&a_corners, &b_corners] {
+            for i in 0..4 {
+                let edge = corners[(i + 1) % 4] - corners[i];
+                let axis = edge.perp().normalize_or_zero();
+                if axis == Vec2::ZERO {
+                    continue; // Degenerate edge
+                }
+
+                let (a_min, a_max) = Self::project_onto_axis(&a_corners, axis);
+                let (b_min, b_max) = Self::project_onto_axis(&b_corners, axis);
+                let depth = a_max.min(b_max) - a_min.max(b_min);
+                if depth < 0.0 {
+                    return None;
+                }
+
+                if best.is_none_or(|(_, best_depth)| depth < best_depth) {
+                    best = Some((axis, depth));
+                }
+            }
+        }
+
+        let (mut axis, depth) = best?;
+        let a_center = a_corners.iter().sum::<Vec2>() / 4.0;
+        let b_center = b_corners.iter().sum::<Vec2>() / 4.0;
+        if axis.dot(a_center - b_center) < 0.0 {
+            axis = -axis;
+        }
+        Some((axis.extend(0.0), depth))
+    }
+
+    /// Tests a circle against the transformed quad. Returns the normal pointing
+    /// from the quad towards the circle and how deep the circle penetrates, so
+    /// moving the circle by `normal * depth` separates them.
+    pub fn circle_vs_space(center: Vec2, radius: f32, space: &Transform) -> Option<(Vec3, f32)> {
+        let corners = Self::get_world_corners(space).map(|c| c.truncate());
+
+        // The winding depends on the transform, so orient edge normals by it
+        let winding = (corners[1] - corners[0]).perp_dot(corners[2] - corners[1]);
+        let mut inside = true;
+        let mut closest: Option<(Vec2, f32)> = None; // (point on the boundary, distance)
+        let mut shallowest: Option<(Vec2, f32)> = None; // (outward normal, distance to edge)
+
+        for i in 0..4 {
+            let start = corners[i];
+            let end = corners[(i + 1) % 4];
+            let edge = end - start;
+            let length_squared = edge.length_squared();
+            if length_squared <= f32::EPSILON {
+                continue; // Degenerate edge
+            }
+
+            let mut outward = -edge.perp().normalize();
+            if winding < 0.0 {
+                outward = -outward;
+            }
+            let signed_distance = (center - start).dot(outward);
+            if signed_distance > 0.0 {
+                inside = false;
+            }
+            if shallowest.is_none_or(|(_, d)| -signed_distance < d) {
+                shallowest = Some((outward, -signed_distance));
+            }
+
+            let t = ((center - start).dot(edge) / length_squared).clamp(0.0, 1.0);
+            let point = start + edge * t;
+            let distance = center.distance(point);
+            if closest.is_none_or(|(_, d)| distance < d) {
+                closest = Some((point, distance));
+            }
+        }
+
+        if inside {
+            // The center is in the quad, push out through the nearest edge
+            let (normal, distance) = shallowest?;
+            return Some((normal.extend(0.0), distance + radius));
+        }
+
+        let (point, distance) = closest?;
+        if distance >= radius || distance <= f32::EPSILON {
+            return None;
+        }
+        let normal = (center - point) / distance;
+        Some((normal.extend(0.0), radius - distance))
+    }
+
+    /// Casts a ray from `origin` along `dir` against the transformed quad and
+    /// returns the nearest hit in front of the origin. A ray starting inside
+    /// the quad hits right away, at distance 0 with the normal facing `-dir`.
+    pub fn raycast(origin: Vec2, dir: Vec2, space: &Transform) -> Option<RayHit> {
+        if dir.length_squared() <= f32::EPSILON {
+            return None;
+        }
+
+        // Slab test against the unit square, in the quad's local space. The
+        // ray parameter is the same in both spaces since the map is affine.
+        let to_local = space.matrix().inverse();
+        let local_origin = to_local.transform_point3(origin.extend(0.0)).truncate();
+        let local_dir = to_local.transform_vector3(dir.extend(0.0)).truncate();
+
+        let mut t_enter = f32::NEG_INFINITY;
+        let mut t_exit = f32::INFINITY;
+        let mut enter_normal = Vec2::ZERO;
+        for axis in 0..2 {
+            let o = local_origin[axis];
+            let d = local_dir[axis];
+            if d.abs() <= f32::EPSILON {
+                // Parallel to this slab, so it has to start between its sides
+                if !(0.0..=1.0).contains(&o) {
+                    return None;
+                }
+                continue;
+            }
+            let mut near = -o / d;
+            let mut far = (1.0 - o) / d;
+            let mut normal = -Vec2::AXES[axis];
+            if near > far {
+                std::mem::swap(&mut near, &mut far);
+                normal = -normal;
+            }
+            if near > t_enter {
+                t_enter = near;
+                enter_normal = normal;
+            }
+            t_exit = t_exit.min(far);
+        }
+        if t_enter > t_exit || t_exit < 0.0 {
+            return None;
+        }
+
+        if t_enter < 0.0 {
+            return Some(RayHit {
+                distance: 0.0,
+                point: origin,
+                normal: -dir.normalize(),
+            });
+        }
+        // Normals go back to world space through the inverse transpose
+        let normal = to_local
+            .transpose()
+            .transform_vector3(enter_normal.extend(0.0))
+            .truncate()
+            .normalize();
+        Some(RayHit {
+            distance: t_enter * dir.length(),
+            point: origin + dir * t_enter,
+            normal,
+        })
+    }
+
+    fn project_onto_axis(corners: &[Vec2; 4], axis: Vec2) -> (f32, f32) {
+        corners
+            .iter()
+            .map(|corner| corner.dot(axis))
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), p| {
+                (min.min(p), max.max(p))
+            })
+    }
+
+    fn get_world_aabb(transform: &Transform) -> (Vec3, Vec3) {
+        Self::corners_aabb(&Self::get_world_corners(transform))
+    }
+
+    fn corners_aabb(corners: &[Vec3; 4]) -> (Vec3, Vec3) {
+        let min = corners.iter().copied().fold(Vec3::INFINITY, Vec3::min);
+        let max = corners.iter().copied().fold(Vec3::NEG_INFINITY, Vec3::max);
+        (min, max)
+    }
+
     fn check_vertices_in_space(from: &Transform, to: &Transform) -> VertexCollision {
         let transform = from.map_towards(to);
 
@@ -153,7 +478,7 @@ impl Collision {
 
         let in_bounds: Vec<bool> = projected
             .iter()
-            .map(|corner| corner.x >= 0.0 && corner.x <= 1.0 && corner.y >= 0.0 && corner.y <= 1.0)
+            .map(|corner| (0.0..=1.0).contains(&corner.x) && (0.0..=1.0).contains(&corner.y))
             .collect();
 
         VertexCollision {
@@ -228,7 +553,7 @@ impl Collision {
         let t = ((p1.x - p3.x) * (p3.y - p4.y) - (p1.y - p3.y) * (p3.x - p4.x)) / denom;
         let u = -((p1.x - p2.x) * (p1.y - p3.y) - (p1.y - p2.y) * (p1.x - p3.x)) / denom;
 
-        if t >= 0.0 && t <= 1.0 && u >= 0.0 && u <= 1.0 {
+        if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
             // Calculate intersection point
             let intersection_x = p1.x + t * (p2.x - p1.x);
             let intersection_y = p1.y + t * (p2.y - p1.y);
@@ -253,6 +578,69 @@ impl Collision {
     }
 }
 
+/// Buckets quads into square cells by their world AABB, so only quads that
+/// share a cell need the full `Collision::do_spaces_collide` test.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl SpatialGrid {
+    /// `cell_size` is in world units; around the size of a typical object works well.
+    /// Sizes that aren't finite and positive would make `insert` loop over
+    /// billions of cells, so they fall back to 1.
+    pub fn new(cell_size: f32) -> Self {
+        let cell_size = if cell_size.is_finite() && cell_size > 0.0 {
+            cell_size
+        } else {
+            log::warn!("Invalid spatial grid cell size {cell_size}, using 1");
+            1.0
+        };
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Adds the quad `space` under `id`, in every cell its AABB touches.
+    pub fn insert(&mut self, id: usize, space: &Transform) {
+        let (min, max) = Collision::get_world_aabb(space);
+        let (min_x, min_y) = self.cell_of(min.truncate());
+        let (max_x, max_y) = self.cell_of(max.truncate());
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                self.cells.entry((x, y)).or_default().push(id);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    /// Every pair of ids sharing a cell, once each and with the smaller id
+    /// first. These are only candidates: their AABBs are close, but the quads
+    /// themselves may not touch.
+    pub fn potential_pairs(&self) -> impl Iterator<Item = (usize, usize)> {
+        let mut pairs = BTreeSet::new();
+        for ids in self.cells.values() {
+            for (i, &a) in ids.iter().enumerate() {
+                for &b in &ids[i + 1..] {
+                    if a != b {
+                        pairs.insert((a.min(b), a.max(b)));
+                    }
+                }
+            }
+        }
+        pairs.into_iter()
+    }
+
+    fn cell_of(&self, point: Vec2) -> (i32, i32) {
+        let cell = (point / self.cell_size).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+}
+
 // Usage example:
 /*
 if let Some(collision) = CollisionInfo::do_spaces_collide(&player_transform, &wall_transform) {
@@ -280,3 +668,259 @@ if let Some(collision) = CollisionInfo::do_spaces_collide(&player_transform, &wa
     }
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(x: f32, y: f32, size: f32) -> Transform {
+        Transform::from_trs(Vec3::new(x, y, 0.0), 0.0, Vec3::splat(size))
+    }
+
+    #[test]
+    fn separate_quads_do_not_collide() {
+        assert!(Collision::do_spaces_collide(&quad(0.0, 0.0, 1.0), &quad(3.0, 0.0, 1.0)).is_none());
+    }
+
+    #[test]
+    fn resolution_pushes_out_along_the_shallowest_axis() {
+        let collision =
+            Collision::do_spaces_collide(&quad(0.0, 0.0, 1.0), &quad(0.75, 0.25, 1.0)).unwrap();
+        let (normal, depth) = collision.resolution().unwrap();
+
+        assert_eq!(normal, Vec3::new(-1.0, 0.0, 0.0));
+        assert!((depth - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sweep_finds_the_time_of_impact() {
+        let moving = quad(0.0, 0.0, 1.0);
+        let target = quad(3.0, 0.0, 1.0);
+        let from = Vec2::new(0.5, 0.5);
+
+        let t = Collision::sweep_space(&moving, from, from + Vec2::new(4.0, 0.0), &target);
+        assert!((t.unwrap() - 0.5).abs() < 1e-5);
+        assert!(
+            Collision::sweep_space(&moving, from, from + Vec2::new(0.0, 4.0), &target).is_none()
+        );
+    }
+
+    #[test]
+    fn sweep_catches_a_fast_ball_tunneling_through_a_thin_paddle() {
+        let paddle = Transform::from_trs(Vec3::new(0.5, 0.4, 0.0), 0.0, Vec3::new(0.01, 0.2, 1.0));
+        let ball = quad(0.3, 0.49, 0.02);
+        let from = Vec2::new(0.31, 0.5);
+        let to = from + Vec2::new(0.4, 0.0);
+
+        // Checked only where each step lands, the ball skips right over it
+        let landed = quad(0.7, 0.49, 0.02);
+        assert!(Collision::do_spaces_collide(&ball, &paddle).is_none());
+        assert!(Collision::do_spaces_collide(&landed, &paddle).is_none());
+
+        // The ball's leading edge reaches x = 0.5 after (0.49 - 0.31) / 0.4
+        let t = Collision::sweep_space(&ball, from, to, &paddle).unwrap();
+        assert!((t - 0.45).abs() < 1e-5);
+    }
+
+    #[test]
+    fn sat_overlap_handles_rotated_quads() {
+        assert!(Collision::sat_overlap(&quad(0.0, 0.0, 1.0), &quad(3.0, 0.0, 1.0)).is_none());
+
+        let diamond = Transform::from_trs(
+            Vec3::new(2.0, 0.0, 0.0),
+            std::f32::consts::FRAC_PI_4,
+            Vec3::ONE,
+        );
+        let (axis, depth) = Collision::sat_overlap(&quad(1.0, 0.0, 1.0), &diamond).unwrap();
+
+        assert!(depth > 0.0);
+        // A is left of the diamond, so it gets pushed left
+        assert!(axis.x < 0.0);
+    }
+
+    #[test]
+    fn sat_separates_parallel_diagonal_sticks_with_overlapping_boxes() {
+        let stick = |x: f32, y: f32| {
+            Transform::from_trs(
+                Vec3::new(x, y, 0.0),
+                std::f32::consts::FRAC_PI_4,
+                Vec3::new(2.0, 0.1, 1.0),
+            )
+        };
+        // Side by side along the diagonal, 0.707 apart and only 0.1 thick
+        let (a, b) = (stick(0.0, 0.0), stick(0.5, -0.5));
+
+        let (a_min, a_max) = Collision::get_world_aabb(&a);
+        let (b_min, b_max) = Collision::get_world_aabb(&b);
+        assert!(a_min.cmple(b_max).all() && b_min.cmple(a_max).all());
+        assert!(Collision::sat_overlap(&a, &b).is_none());
+        assert!(Collision::do_spaces_collide(&a, &b).is_none());
+    }
+
+    #[test]
+    fn circle_touching_an_edge_is_pushed_out() {
+        let (normal, depth) =
+            Collision::circle_vs_space(Vec2::new(1.2, 0.5), 0.3, &quad(0.0, 0.0, 1.0)).unwrap();
+
+        assert!(normal.abs_diff_eq(Vec3::X, 1e-5));
+        assert!((depth - 0.1).abs() < 1e-5);
+        assert!(
+            Collision::circle_vs_space(Vec2::new(2.0, 0.5), 0.3, &quad(0.0, 0.0, 1.0)).is_none()
+        );
+    }
+
+    #[test]
+    fn circle_centered_inside_leaves_through_the_nearest_edge() {
+        let (normal, depth) =
+            Collision::circle_vs_space(Vec2::new(0.5, 0.3), 0.1, &quad(0.0, 0.0, 1.0)).unwrap();
+
+        // 0.3 to the y = 0 edge plus the whole radius
+        assert!(normal.abs_diff_eq(Vec3::NEG_Y, 1e-5));
+        assert!((depth - 0.4).abs() < 1e-5);
+    }
+
+    #[test]
+    fn circle_over_a_corner_is_pushed_away_from_it() {
+        let (normal, depth) =
+            Collision::circle_vs_space(Vec2::new(1.2, 1.2), 0.5, &quad(0.0, 0.0, 1.0)).unwrap();
+
+        let diagonal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        assert!(normal.abs_diff_eq(diagonal, 1e-5));
+        assert!((depth - (0.5 - 0.2 * std::f32::consts::SQRT_2)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn spatial_grid_pairs_only_nearby_quads() {
+        let mut grid = SpatialGrid::new(1.0);
+        grid.insert(0, &quad(0.1, 0.1, 0.5));
+        grid.insert(1, &quad(10.1, 10.1, 0.5));
+        assert_eq!(grid.potential_pairs().count(), 0);
+
+        grid.insert(2, &quad(0.3, 0.3, 0.5));
+        assert_eq!(grid.potential_pairs().collect::<Vec<_>>(), vec![(0, 2)]);
+
+        grid.clear();
+        assert_eq!(grid.potential_pairs().count(), 0);
+    }
+
+    #[test]
+    fn spatial_grid_falls_back_from_invalid_cell_sizes() {
+        for cell_size in [0.0, -2.0, f32::NAN, f32::INFINITY] {
+            let mut grid = SpatialGrid::new(cell_size);
+            assert_eq!(grid.cell_size, 1.0);
+
+            grid.insert(0, &quad(0.1, 0.1, 0.5));
+            grid.insert(1, &quad(0.3, 0.3, 0.5));
+            assert_eq!(grid.cells.len(), 1);
+            assert_eq!(grid.potential_pairs().collect::<Vec<_>>(), vec![(0, 1)]);
+        }
+    }
+
+    #[test]
+    fn raycast_hits_the_near_face() {
+        let hit = Collision::raycast(Vec2::new(-1.0, 1.0), Vec2::X, &quad(0.0, 0.0, 2.0)).unwrap();
+
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+        assert!(hit.point.abs_diff_eq(Vec2::new(0.0, 1.0), 1e-5));
+        assert!(hit.normal.abs_diff_eq(Vec2::NEG_X, 1e-5));
+    }
+
+    #[test]
+    fn raycast_misses_behind_and_beside() {
+        let space = quad(0.0, 0.0, 2.0);
+
+        assert!(Collision::raycast(Vec2::new(-1.0, 1.0), Vec2::NEG_X, &space).is_none());
+        assert!(Collision::raycast(Vec2::new(-1.0, 3.0), Vec2::X, &space).is_none());
+    }
+
+    #[test]
+    fn raycast_from_inside_hits_immediately() {
+        let hit = Collision::raycast(Vec2::new(1.0, 1.0), Vec2::Y, &quad(0.0, 0.0, 2.0)).unwrap();
+
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.point, Vec2::new(1.0, 1.0));
+        assert!(hit.normal.abs_diff_eq(Vec2::NEG_Y, 1e-5));
+    }
+
+    #[test]
+    fn contact_centroid_is_between_the_crossings() {
+        // B overlaps A's right edge, crossing it at y = 0.25 and y = 0.75
+        let collision = Collision::do_spaces_collide(
+            &quad(0.0, 0.0, 1.0),
+            &Transform::from_trs(Vec3::new(0.5, 0.25, 0.0), 0.0, Vec3::new(1.0, 0.5, 1.0)),
+        )
+        .unwrap();
+        let centroid = collision.contact_centroid().unwrap();
+
+        assert!(centroid.abs_diff_eq(Vec3::new(1.0, 0.5, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn contained_quad_has_no_contact_centroid() {
+        let collision =
+            Collision::do_spaces_collide(&quad(0.0, 0.0, 4.0), &quad(1.0, 1.0, 1.0)).unwrap();
+
+        assert!(collision.contact_centroid().is_none());
+    }
+
+    #[test]
+    fn touching_boxes_overlap_and_rotation_grows_the_box() {
+        assert!(Collision::aabb_overlap(
+            &quad(0.0, 0.0, 1.0),
+            &quad(1.0, 0.0, 1.0)
+        ));
+        assert!(!Collision::aabb_overlap(
+            &quad(0.0, 0.0, 1.0),
+            &quad(1.5, 0.0, 1.0)
+        ));
+        // Turned 45 degrees around its corner, the quad reaches 0.707 to the
+        // left of it, past the end of the first one
+        let diamond = Transform::from_trs(
+            Vec3::new(1.6, 0.0, 0.0),
+            std::f32::consts::FRAC_PI_4,
+            Vec3::ONE,
+        );
+        assert!(Collision::aabb_overlap(&quad(0.0, 0.0, 1.0), &diamond));
+        assert!(Collision::do_spaces_collide(&quad(0.0, 0.0, 1.0), &diamond).is_some());
+    }
+
+    #[test]
+    fn separated_pairs_skip_the_full_test() {
+        FULL_TESTS.set(0);
+        assert!(Collision::do_spaces_collide(&quad(0.0, 0.0, 1.0), &quad(3.0, 0.0, 1.0)).is_none());
+        assert_eq!(FULL_TESTS.get(), 0);
+
+        assert!(Collision::do_spaces_collide(&quad(0.0, 0.0, 1.0), &quad(0.5, 0.0, 1.0)).is_some());
+        assert_eq!(FULL_TESTS.get(), 1);
+    }
+
+    // cargo test --release --target <host> bench_separated_pairs -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_separated_pairs() {
+        const PAIRS: usize = 200_000;
+        let pairs: Vec<_> = (0..PAIRS)
+            .map(|i| (quad(0.0, 0.0, 1.0), quad(2.0 + i as f32 * 1e-3, 0.5, 1.0)))
+            .collect();
+        let time = |test: fn(&Transform, &Transform) -> Option<Collision>| {
+            let start = std::time::Instant::now();
+            for (a, b) in &pairs {
+                assert!(std::hint::black_box(test(a, b)).is_none());
+            }
+            start.elapsed()
+        };
+
+        let full = time(Collision::full_test);
+        FULL_TESTS.set(0);
+        let early_out = time(Collision::do_spaces_collide);
+        // Each full test takes four inverses
+        let inverses_skipped = 4 * (PAIRS - FULL_TESTS.get());
+        println!(
+            "{} separated pairs: {:?} without the bounding boxes, {:?} with them, {} inverses skipped",
+            PAIRS,
+            full,
+            early_out,
+            inverses_skipped
+        );
+    }
+}
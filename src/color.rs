@@ -0,0 +1,310 @@
+use std::ops::{Add, Mul};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+/// Represents a color in RGBA format. The RGB channels are in the renderer's
+/// `ColorSpace`, sRGB unless changed; alpha is always linear.
+pub struct EngineColor {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl EngineColor {
+    pub const WHITE: Self = Self {
+        r: 1.0,
+        g: 1.0,
+        b: 1.0,
+        a: 1.0,
+    };
+    pub const BLACK: Self = Self {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    pub const RED: Self = Self {
+        r: 1.0,
+        g: 0.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    pub const GREEN: Self = Self {
+        r: 0.0,
+        g: 1.0,
+        b: 0.0,
+        a: 1.0,
+    };
+    pub const BLUE: Self = Self {
+        r: 0.0,
+        g: 0.0,
+        b: 1.0,
+        a: 1.0,
+    };
+    pub const PURPLE: Self = Self {
+        r: 0.5,
+        g: 0.0,
+        b: 0.5,
+        a: 1.0,
+    };
+
+    /// Creates a color from a packed `0xRRGGBBAA` value. Channels are mapped
+    /// straight to 0..1 like the constants above, so `0xFF0000FF` is `RED`.
+    pub fn from_hex(hex: u32) -> Self {
+        Self {
+            r: ((hex >> 24) & 0xFF) as f32 / 255.0,
+            g: ((hex >> 16) & 0xFF) as f32 / 255.0,
+            b: ((hex >> 8) & 0xFF) as f32 / 255.0,
+            a: (hex & 0xFF) as f32 / 255.0,
+        }
+    }
+
+    /// Parses `#RRGGBB` or `#RRGGBBAA` (the `#` is optional). Colors without
+    /// an alpha component are fully opaque.
+    pub fn from_hex_str(hex: &str) -> Result<Self, ParseColorError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseColorError::InvalidDigit);
+        }
+        // Checked before parsing, more than 8 digits would overflow the u32
+        let len = digits.len();
+        if len != 6 && len != 8 {
+            return Err(ParseColorError::InvalidLength(len));
+        }
+        let value = u32::from_str_radix(digits, 16).map_err(|_| ParseColorError::InvalidDigit)?;
+        if len == 6 {
+            Ok(Self::from_hex((value << 8) | 0xFF))
+        } else {
+            Ok(Self::from_hex(value))
+        }
+    }
+
+    /// Creates a color from hue (degrees, wrapped to 0..360), saturation and
+    /// value (both 0..1), and alpha.
+    pub fn from_hsv(h: f32, s: f32, v: f32, a: f32) -> Self {
+        let h = h.rem_euclid(360.0);
+        let s = s.clamp(0.0, 1.0);
+        let v = v.clamp(0.0, 1.0);
+
+        let chroma = v * s;
+        let x = chroma * (1.0 - ((h / 60.0) % 2.0 - 1.0).abs());
+        let m = v - chroma;
+
+        let (r, g, b) = match (h / 60.0) as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+
+        Self {
+            r: r + m,
+            g: g + m,
+            b: b + m,
+            a,
+        }
+    }
+
+    /// Blends channel by channel, `self` at `t = 0` and `other` at `t = 1`.
+    pub fn lerp(self, other: Self, t: f32) -> Self {
+        self * (1.0 - t) + other * t
+    }
+
+    /// Decodes gamma encoded sRGB channels to linear light. Alpha is unchanged.
+    pub fn to_linear(self) -> Self {
+        fn decode(c: f32) -> f32 {
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        }
+        Self {
+            r: decode(self.r),
+            g: decode(self.g),
+            b: decode(self.b),
+            a: self.a,
+        }
+    }
+
+    /// Encodes linear channels as sRGB, the inverse of `to_linear`.
+    pub fn to_srgb(self) -> Self {
+        fn encode(c: f32) -> f32 {
+            if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            }
+        }
+        Self {
+            r: encode(self.r),
+            g: encode(self.g),
+            b: encode(self.b),
+            a: self.a,
+        }
+    }
+
+    /// The color with RGB multiplied by alpha, as premultiplied blending expects.
+    pub fn premultiplied(self) -> Self {
+        Self {
+            r: self.r * self.a,
+            g: self.g * self.a,
+            b: self.b * self.a,
+            a: self.a,
+        }
+    }
+}
+
+/// Scales every channel, alpha included.
+impl Mul<f32> for EngineColor {
+    type Output = Self;
+
+    fn mul(self, factor: f32) -> Self {
+        Self {
+            r: self.r * factor,
+            g: self.g * factor,
+            b: self.b * factor,
+            a: self.a * factor,
+        }
+    }
+}
+
+/// Adds channel by channel, alpha included. Nothing is clamped.
+impl Add for EngineColor {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            r: self.r + other.r,
+            g: self.g + other.g,
+            b: self.b + other.b,
+            a: self.a + other.a,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseColorError {
+    InvalidLength(usize),
+    InvalidDigit,
+}
+
+impl std::fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseColorError::InvalidLength(len) => {
+                write!(f, "expected 6 or 8 hex digits, got {}", len)
+            }
+            ParseColorError::InvalidDigit => write!(f, "invalid hex digit"),
+        }
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hex_maps_channels_to_the_constants() {
+        assert_eq!(EngineColor::from_hex(0xFFFFFFFF), EngineColor::WHITE);
+        assert_eq!(EngineColor::from_hex(0x000000FF), EngineColor::BLACK);
+        assert_eq!(EngineColor::from_hex(0xFF0000FF), EngineColor::RED);
+        assert_eq!(EngineColor::from_hex(0xFFFFFF00).a, 0.0);
+    }
+
+    #[test]
+    fn from_hsv_hits_the_primaries() {
+        assert_eq!(EngineColor::from_hsv(0.0, 1.0, 1.0, 1.0), EngineColor::RED);
+        assert_eq!(
+            EngineColor::from_hsv(120.0, 1.0, 1.0, 1.0),
+            EngineColor::GREEN
+        );
+        assert_eq!(
+            EngineColor::from_hsv(240.0, 1.0, 1.0, 1.0),
+            EngineColor::BLUE
+        );
+        // Hue wraps around and zero saturation is grey
+        assert_eq!(
+            EngineColor::from_hsv(360.0, 1.0, 1.0, 1.0),
+            EngineColor::RED
+        );
+        assert_eq!(
+            EngineColor::from_hsv(-120.0, 1.0, 1.0, 1.0),
+            EngineColor::BLUE
+        );
+        assert_eq!(
+            EngineColor::from_hsv(77.0, 0.0, 1.0, 1.0),
+            EngineColor::WHITE
+        );
+    }
+
+    #[test]
+    fn from_hex_str_parses_with_and_without_alpha() {
+        assert_eq!(EngineColor::from_hex_str("#FF0000"), Ok(EngineColor::RED));
+        assert_eq!(EngineColor::from_hex_str("0000ffff"), Ok(EngineColor::BLUE));
+        assert_eq!(
+            EngineColor::from_hex_str("#ffffff00"),
+            Ok(EngineColor::from_hex(0xFFFFFF00))
+        );
+    }
+
+    #[test]
+    fn from_hex_str_rejects_bad_lengths_and_digits() {
+        assert_eq!(
+            EngineColor::from_hex_str("#FFF"),
+            Err(ParseColorError::InvalidLength(3))
+        );
+        // Too long for a u32, still reported as a length problem
+        assert_eq!(
+            EngineColor::from_hex_str("#FFFFFFFFFF"),
+            Err(ParseColorError::InvalidLength(10))
+        );
+        assert_eq!(
+            EngineColor::from_hex_str("#GG0000"),
+            Err(ParseColorError::InvalidDigit)
+        );
+        assert_eq!(
+            EngineColor::from_hex_str("#+FFFFF"),
+            Err(ParseColorError::InvalidDigit)
+        );
+    }
+
+    #[test]
+    fn lerp_halfway_from_black_to_white_is_grey() {
+        let grey = EngineColor::BLACK.lerp(EngineColor::WHITE, 0.5);
+
+        assert_eq!(
+            grey,
+            EngineColor {
+                r: 0.5,
+                g: 0.5,
+                b: 0.5,
+                a: 1.0
+            }
+        );
+        assert_eq!(
+            EngineColor::BLACK.lerp(EngineColor::WHITE, 0.0),
+            EngineColor::BLACK
+        );
+        assert_eq!(
+            EngineColor::BLACK.lerp(EngineColor::WHITE, 1.0),
+            EngineColor::WHITE
+        );
+    }
+
+    #[test]
+    fn premultiplied_scales_rgb_but_keeps_alpha() {
+        let color = EngineColor::from_hsv(0.0, 0.0, 0.8, 0.25).premultiplied();
+
+        assert!((color.r - 0.2).abs() < 1e-6);
+        assert!((color.g - 0.2).abs() < 1e-6);
+        assert!((color.b - 0.2).abs() < 1e-6);
+        assert_eq!(color.a, 0.25);
+        assert_eq!(EngineColor::RED.premultiplied(), EngineColor::RED);
+    }
+}
@@ -1,283 +1,798 @@
 use glam::{Vec2, Vec3};
-use log::info;
-use wgpu::Color;
-use winit::keyboard::KeyCode;
+use serde::Serialize;
+use winit::keyboard::{Key, KeyCode};
 
 use crate::{
     audio::{AudioHandle, AudioSystem},
-    collision::Collision,
+    color::EngineColor,
     geometry::Transform,
-    renderer::{Drawer, EngineColor, RenderingSystem},
-    InputSystem,
+    input::InputSystem,
+    particles::{EmitConfig, ParticleSystem},
+    pong::{
+        Action, AiController, Ball, BounceResponse, Controls, Difficulty, DualPaddleState,
+        GameConfig, Player, StepEvents,
+    },
+    renderer::{Drawer, PostEffect, RenderTarget, RenderingSystem, ScalingMode},
+    rng::Rng,
+    text::BitmapFont,
 };
 
-struct PaddleState {
-    position: f32,
-    last_velocity: f32,
-}
+// The ball's sounds, kept out of pong.rs so the physics build natively
+impl Ball {
+    // Wall hits closer together than this only play one sound
+    const WALL_SOUND_COOLDOWN_MS: f64 = 60.0;
 
-impl Default for PaddleState {
-    fn default() -> Self {
-        Self {
-            position: 0.5,
-            last_velocity: 0.0,
+    // The sounds and the obstacles are separate so the game can keep them
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        delta_time: f32,
+        paddles: &mut DualPaddleState,
+        obstacles: &[Transform],
+        config: &GameConfig,
+        bounce_sound: &AudioHandle,
+        wall_sound: &AudioHandle,
+        audio_system: &mut AudioSystem,
+    ) -> StepEvents {
+        let ortho_si = Transform::ortographic_size_invariant();
+        let events = self.step(delta_time, paddles, obstacles, &ortho_si, config);
+        // A ball grazing the wall can bounce on consecutive steps
+        if let Some(speed) = events.wall_hit {
+            if !audio_system.played_within(wall_sound, Self::WALL_SOUND_COOLDOWN_MS) {
+                audio_system.play_panned(wall_sound, speed, self.pan(config));
+            }
+        }
+        if let Some((player, speed)) = events.paddle_hit {
+            audio_system.play_panned(bounce_sound, speed, self.pan(config));
+            paddles.flash(player);
         }
+        events
     }
 }
 
-impl PaddleState {
-    const PADDLE_WIDTH: f32 = 0.2;
-    const PADDLE_HEIGHT: f32 = PaddleState::PADDLE_WIDTH / 4.0;
-    const PADDLE_SPEED: f32 = 0.5; // Speed in normalized units
+/// What the game is showing, see `Game::screen`. Each screen reads its own
+/// keys in `Game::update` and draws itself in `Game::render`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Screen {
+    /// The title menu, picked from with the arrow keys and Enter.
+    Menu,
+    Playing,
+    /// The court is frozen, P resumes and period advances a single step.
+    Paused,
+    /// Someone reached the target score. Space starts a rematch, Escape goes
+    /// back to the menu.
+    GameOver,
+}
+
+// A line of the title menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MenuItem {
+    Start,
+    Opponent,
+    // Left and right pick the action, Enter listens for its new key
+    Rebind,
+}
+
+impl MenuItem {
+    const ALL: [MenuItem; 3] = [MenuItem::Start, MenuItem::Opponent, MenuItem::Rebind];
+}
+
+/// Where a match is, see `Game::match_state`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchState {
+    Playing,
+    /// The ball waits at the center after a goal, and launches once
+    /// `remaining` seconds are up. Paddles can still move.
+    ServeCountdown {
+        remaining: f32,
+    },
+    /// Someone reached the target score, see `Game::winner`.
+    GameOver,
+}
+
+/// A ball in a `GameSnapshot`, in the 0..1 court space.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BallSnapshot {
+    pub center: [f32; 2],
+    /// Normalized units per second.
+    pub velocity: [f32; 2],
+}
+
+/// A read-only copy of what a web overlay might show, see `Game::snapshot`.
+/// Positions are in the 0..1 court space with y pointing down, so player A's
+/// paddle is at the top.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GameSnapshot {
+    pub balls: Vec<BallSnapshot>,
+    /// Horizontal centers of the paddles.
+    pub paddle_a: f32,
+    pub paddle_b: f32,
+    pub score_a: u32,
+    pub score_b: u32,
+}
+
+impl GameSnapshot {
+    /// The snapshot as a JSON object with the field names above, for JS.
+    pub fn to_json(&self) -> String {
+        // Only numbers and arrays of them, which always serialize
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
 
-    pub fn local_space(&self, ortho_si: &Transform, is_player_a: bool) -> Transform {
-        // Position the origin at the top left
+pub struct Game {
+    paddles: DualPaddleState,
+    balls: Vec<Ball>,
+    bouce_sound: AudioHandle,
+    wall_sound: AudioHandle,
+    font: BitmapFont,
+    particles: ParticleSystem,
+    // Picks serve directions
+    rng: Rng,
+    // Drives player B when set, for single-player games
+    ai_player_b: Option<AiController>,
+    score_a: u32,
+    score_b: u32,
+    target_score: u32,
+    // Applied by the renderer every frame, see post_effect
+    post_effect: PostEffect,
+    screen: Screen,
+    // Index into MenuItem::ALL of the highlighted line
+    menu_selection: usize,
+    // Index into Action::ALL shown on the rebind line
+    rebind_selection: usize,
+    // Draws the grid, velocities and goal regions on top, toggled with F3
+    debug_overlay: bool,
+    // Where to draw the in-game cursor, see InputSystem::custom_cursor_position
+    cursor: Option<Vec2>,
+    // Quads in game space the balls bounce off, for breakout-style modes
+    obstacles: Vec<Transform>,
+    // Whether an obstacle is removed once a ball hits it
+    destroy_obstacles_on_hit: bool,
+    // Obstacles hit during the last update, see obstacle_hits
+    obstacle_hits: Vec<usize>,
+    // Multiplies the match's delta time, see set_time_scale
+    time_scale: f32,
+    background_color: EngineColor,
+    controls: Controls,
+    state: MatchState,
+    config: GameConfig,
+}
 
-        let horizontal_range = 1.0 - PaddleState::PADDLE_WIDTH;
-        let vertical_range = 1.0 - PaddleState::PADDLE_HEIGHT;
+impl Game {
+    pub const DEFAULT_TARGET_SCORE: u32 = 11;
+    const TEXT_HEIGHT: f32 = 2.0 / 30.0; // Two 8px glyph rows at the 240px target height
 
-        let vertical_position = if is_player_a { 0.0 } else { 1.0 };
+    // Diameter of the in-game cursor, as a fraction of the screen height
+    const CURSOR_SIZE: f32 = 0.02;
+    const BOUNCE_PARTICLES: usize = 12;
+    // Seconds the ball waits at the center before a serve
+    const SERVE_DELAY: f32 = 1.5;
 
-        let x = self.position * horizontal_range;
-        let y = vertical_position * vertical_range;
+    pub fn target_size() -> (u32, u32) {
+        (320, 240)
+    }
 
-        ortho_si.translate(Vec3::new(x, y, 0.0)).scale(Vec3 {
-            x: PaddleState::PADDLE_WIDTH,
-            y: PaddleState::PADDLE_HEIGHT,
-            z: 1.0,
-        })
+    pub fn init(
+        rendering_system: &mut RenderingSystem,
+        audio_system: &mut AudioSystem,
+        config: GameConfig,
+    ) -> Self {
+        Self::new_with_seed(rendering_system, audio_system, config, Rng::time_seed())
     }
 
-    pub fn goal_local_space(&self, ortho_si: &Transform, is_player_a: bool) -> Transform {
-        let vertical_position = if is_player_a { 0.0 } else { 1.0 };
+    /// Like `init`, but serves follow the sequence given by `seed`, so a match
+    /// plays out the same way every time.
+    pub fn new_with_seed(
+        rendering_system: &mut RenderingSystem,
+        audio_system: &mut AudioSystem,
+        config: GameConfig,
+        seed: u64,
+    ) -> Self {
+        // Draw at the 320x240 target size and scale up, so pixels stay crisp
+        rendering_system.set_render_target(RenderTarget::Offscreen);
+        rendering_system.set_scaling_mode(ScalingMode::IntegerNearest);
+        let mut rng = Rng::new(seed);
+        Self {
+            paddles: DualPaddleState::default(),
+            balls: vec![Ball::serve(&mut rng, &config)],
+            rng,
+            bouce_sound: audio_system.load_buffer(include_bytes!("assets/bounce_1.wav")),
+            wall_sound: audio_system.load_buffer(include_bytes!("assets/wall_1.wav")),
+            font: rendering_system.load_font(include_bytes!("assets/font_8x8.png")),
+            particles: ParticleSystem::default(),
+            ai_player_b: None,
+            score_a: 0,
+            score_b: 0,
+            target_score: Self::DEFAULT_TARGET_SCORE,
+            post_effect: PostEffect::None,
+            screen: Screen::Menu,
+            menu_selection: 0,
+            rebind_selection: 0,
+            debug_overlay: false,
+            cursor: None,
+            obstacles: Vec::new(),
+            destroy_obstacles_on_hit: false,
+            obstacle_hits: Vec::new(),
+            time_scale: 1.0,
+            background_color: EngineColor::BLACK,
+            controls: Controls::default(),
+            state: MatchState::ServeCountdown {
+                remaining: Self::SERVE_DELAY,
+            },
+            config,
+        }
+    }
 
-        let vertical_range = 1.0 - PaddleState::PADDLE_HEIGHT;
+    /// Hands player B to an AI of the given difficulty, or back to the arrow
+    /// keys with `None`.
+    pub fn set_ai_opponent(&mut self, difficulty: Option<Difficulty>) {
+        self.ai_player_b = difficulty.map(AiController::new);
+    }
 
-        let y = vertical_position * vertical_range;
+    pub fn ai_opponent(&self) -> Option<Difficulty> {
+        self.ai_player_b.as_ref().map(AiController::difficulty)
+    }
 
-        ortho_si.translate(Vec3::new(0.0, y, 0.0)).scale(Vec3 {
-            x: 1.0,
-            y: PaddleState::PADDLE_HEIGHT,
-            z: 1.0,
-        })
+    /// Adds another ball to the court. `position` is the ball's top-left corner
+    /// in normalized units; only the direction of `velocity` matters since
+    /// balls always travel at the same speed.
+    pub fn spawn_ball(&mut self, position: Vec2, velocity: Vec2) {
+        self.balls.push(Ball::new(position, velocity));
     }
 
-    pub fn move_left(&mut self, delta_time: f32) {
-        self.position -= PaddleState::PADDLE_SPEED * delta_time;
-        if self.position < 0.0 {
-            self.position = 0.0;
-        } else {
-            self.last_velocity = -PaddleState::PADDLE_SPEED;
+    /// Copies out the positions and scores, cheap enough to call every frame.
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot {
+            balls: self
+                .balls
+                .iter()
+                .map(|ball| BallSnapshot {
+                    center: ball.center(&self.config).to_array(),
+                    velocity: ball.velocity.to_array(),
+                })
+                .collect(),
+            paddle_a: self.paddles.player_a.center_x(&self.config),
+            paddle_b: self.paddles.player_b.center_x(&self.config),
+            score_a: self.score_a,
+            score_b: self.score_b,
         }
     }
-    pub fn move_right(&mut self, delta_time: f32) {
-        self.position += PaddleState::PADDLE_SPEED * delta_time;
-        if self.position > 1.0 {
-            self.position = 1.0;
+
+    pub fn score(&self) -> (u32, u32) {
+        (self.score_a, self.score_b)
+    }
+
+    pub fn set_target_score(&mut self, target_score: u32) {
+        self.target_score = target_score.max(1);
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    /// Runs the match slower or faster than real time: 0.25 for slow motion
+    /// on a big hit, 0 to freeze it. Menus, input and rendering keep real
+    /// time, and sounds play at their normal speed. Negative scales are
+    /// treated as 0.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        if self.score_a >= self.target_score {
+            Some(Player::A)
+        } else if self.score_b >= self.target_score {
+            Some(Player::B)
         } else {
-            self.last_velocity = PaddleState::PADDLE_SPEED;
+            None
         }
     }
-    pub fn reset_velocity(&mut self) {
-        self.last_velocity = 0.0;
+
+    pub fn background_color(&self) -> EngineColor {
+        self.background_color
     }
-}
 
-#[derive(Default)]
-struct DualPaddleState {
-    player_a: PaddleState,
-    player_b: PaddleState,
-}
+    pub fn set_background_color(&mut self, color: EngineColor) {
+        self.background_color = color;
+    }
+
+    pub fn screen(&self) -> Screen {
+        self.screen
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.screen == Screen::Paused
+    }
+
+    /// Freezes or resumes a match in progress. Does nothing on the menu or
+    /// once the match is over.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.screen = match (self.screen, paused) {
+            (Screen::Playing, true) => Screen::Paused,
+            (Screen::Paused, false) => Screen::Playing,
+            (screen, _) => screen,
+        };
+    }
+
+    pub fn is_debug_overlay_enabled(&self) -> bool {
+        self.debug_overlay
+    }
+
+    /// Shows a grid over the court, each ball's velocity and the outlines of
+    /// the goal regions, which are otherwise invisible.
+    pub fn set_debug_overlay(&mut self, enabled: bool) {
+        self.debug_overlay = enabled;
+    }
+
+    /// The effect the game wants applied to the final image.
+    pub fn post_effect(&self) -> PostEffect {
+        self.post_effect
+    }
+
+    /// Starts a new match with serves drawn from `seed`, like
+    /// `new_with_seed`, so recorded input replays the same way. Settings such
+    /// as the AI opponent and controls carry over.
+    pub fn restart_with_seed(&mut self, seed: u64) {
+        self.rng = Rng::new(seed);
+        // The AI's wobble follows its own clock, which has to start over too
+        self.set_ai_opponent(self.ai_opponent());
+        self.reset();
+    }
+
+    /// Starts a new match, from whatever screen is showing.
+    pub fn reset(&mut self) {
+        self.screen = Screen::Playing;
+        self.paddles = DualPaddleState {
+            bounce: self.paddles.bounce,
+            ..Default::default()
+        };
+        self.balls = vec![Ball::serve(&mut self.rng, &self.config)];
+        self.score_a = 0;
+        self.score_b = 0;
+        self.state = MatchState::ServeCountdown {
+            remaining: Self::SERVE_DELAY,
+        };
+    }
+
+    pub fn match_state(&self) -> MatchState {
+        self.state
+    }
+
+    /// Adds a quad for the balls to bounce off. `space` maps the unit square
+    /// into game space, where the court spans 0 to 1 on both axes. Returns
+    /// its index. Obstacles stay across matches until removed.
+    pub fn add_obstacle(&mut self, space: Transform) -> usize {
+        self.obstacles.push(space);
+        self.obstacles.len() - 1
+    }
+
+    /// Removes the obstacle at `index`, moving the ones after it down by one.
+    pub fn remove_obstacle(&mut self, index: usize) -> Option<Transform> {
+        (index < self.obstacles.len()).then(|| self.obstacles.remove(index))
+    }
+
+    pub fn clear_obstacles(&mut self) {
+        self.obstacles.clear();
+    }
+
+    pub fn obstacles(&self) -> &[Transform] {
+        &self.obstacles
+    }
+
+    /// When set, obstacles disappear once a ball bounces off them.
+    pub fn set_destroy_obstacles_on_hit(&mut self, destroy: bool) {
+        self.destroy_obstacles_on_hit = destroy;
+    }
+
+    pub fn destroy_obstacles_on_hit(&self) -> bool {
+        self.destroy_obstacles_on_hit
+    }
+
+    /// Indices of the obstacles hit during the last update, as they were
+    /// before any hit obstacle was destroyed.
+    pub fn obstacle_hits(&self) -> &[usize] {
+        &self.obstacle_hits
+    }
+
+    pub fn bounce_response(&self) -> BounceResponse {
+        self.paddles.bounce
+    }
+
+    pub fn set_bounce_response(&mut self, bounce: BounceResponse) {
+        self.paddles.bounce = bounce;
+    }
+
+    pub fn config(&self) -> GameConfig {
+        self.config
+    }
 
-impl DualPaddleState {
-    pub fn local_spaces(&self, ortho_si: &Transform) -> (Transform, Transform) {
-        (
-            self.player_a.local_space(ortho_si, true),
-            self.player_b.local_space(ortho_si, false),
-        )
+    /// Takes effect right away, even mid-rally, for live tuning.
+    pub fn set_config(&mut self, config: GameConfig) {
+        self.config = config;
     }
 
-    pub fn move_paddles(&mut self, input: &InputSystem, delta_time: f32) {
-        self.player_a.reset_velocity();
-        self.player_b.reset_velocity();
+    pub fn controls(&self) -> &Controls {
+        &self.controls
+    }
+
+    pub fn controls_mut(&mut self) -> &mut Controls {
+        &mut self.controls
+    }
 
-        if input.is_physical_key_down(KeyCode::KeyA) {
-            self.player_a.move_left(delta_time);
+    /// `delta_time` is real time, the match advances by it times the time
+    /// scale.
+    pub fn update(&mut self, input: &InputSystem, audio_system: &mut AudioSystem, delta_time: f32) {
+        self.cursor = input.custom_cursor_position();
+        let delta_time = delta_time * self.time_scale;
+
+        // Keys pressed to rebind a control mean nothing else
+        if self.controls.update(input) {
+            return;
         }
-        if input.is_physical_key_down(KeyCode::KeyD) {
-            self.player_a.move_right(delta_time);
+
+        // Cycle the post effect: none -> scanlines -> crt
+        if input.is_logical_key_just_pressed(Key::Character("c".into())) {
+            self.post_effect = match self.post_effect {
+                PostEffect::None => PostEffect::Scanlines,
+                PostEffect::Scanlines => PostEffect::Crt,
+                PostEffect::Crt => PostEffect::None,
+            };
         }
-        if input.is_physical_key_down(KeyCode::ArrowLeft) {
-            self.player_b.move_left(delta_time);
+
+        if input.is_physical_key_just_pressed(KeyCode::F3) {
+            self.debug_overlay = !self.debug_overlay;
         }
-        if input.is_physical_key_down(KeyCode::ArrowRight) {
-            self.player_b.move_right(delta_time);
+
+        // Also catches a target score lowered below the current score
+        if self.winner().is_some() && matches!(self.screen, Screen::Playing | Screen::Paused) {
+            self.state = MatchState::GameOver;
+            self.screen = Screen::GameOver;
         }
-    }
-}
 
-struct Ball {
-    position: Vec2,
-    velocity: Vec2,
-}
+        match self.screen {
+            Screen::Menu => self.update_menu(input),
+            Screen::Playing => {
+                self.update_match_keys(input);
+                if input.is_logical_key_just_pressed(Key::Character("p".into())) {
+                    self.screen = Screen::Paused;
+                    return;
+                }
+                self.step_match(input, audio_system, delta_time);
+            }
+            Screen::Paused => {
+                self.update_match_keys(input);
+                if input.is_logical_key_just_pressed(Key::Character("p".into())) {
+                    self.screen = Screen::Playing;
+                } else if input.is_logical_key_just_pressed(Key::Character(".".into())) {
+                    self.step_match(input, audio_system, delta_time);
+                }
+            }
+            Screen::GameOver => {
+                // The match is over, hold everything until a new one is started
+                if input.is_physical_key_just_pressed(KeyCode::Space) {
+                    self.reset();
+                } else if input.is_physical_key_just_pressed(KeyCode::Escape) {
+                    self.screen = Screen::Menu;
+                }
+            }
+        }
+    }
 
-impl Ball {
-    const RADIUS: f32 = 0.05; // Radius in normalized units
-    const BALL_SPEED: f32 = 0.5; // Speed in normalized units
+    fn cycle_ai_opponent(&mut self) {
+        // Human -> easy -> normal -> hard AI
+        self.set_ai_opponent(match self.ai_opponent() {
+            None => Some(Difficulty::Easy),
+            Some(Difficulty::Easy) => Some(Difficulty::Normal),
+            Some(Difficulty::Normal) => Some(Difficulty::Hard),
+            Some(Difficulty::Hard) => None,
+        });
+    }
 
-    pub fn update(
-        &mut self,
-        delta_time: f32,
-        paddles: &DualPaddleState,
-        ortho_si: &Transform,
-        bounce_sound: &AudioHandle,
-        wall_sound: &AudioHandle,
-        audio_system: &mut AudioSystem,
-    ) {
-        // At every update, convert some percentage of x velocity into y velocity
-        let amount = self.velocity.x * 0.3 * delta_time;
-        self.velocity.x -= amount;
-        if self.velocity.y > 0.0 {
-            self.velocity.y += amount;
-        } else {
-            self.velocity.y -= amount;
+    // Up and down move the highlight, wrapping around, Enter picks the line
+    fn update_menu(&mut self, input: &InputSystem) {
+        let count = MenuItem::ALL.len();
+        if input.is_physical_key_just_pressed(KeyCode::ArrowUp) {
+            self.menu_selection = (self.menu_selection + count - 1) % count;
         }
-        self.velocity = self.velocity.normalize() * Ball::BALL_SPEED; // Normalize speed
-        self.position += self.velocity * delta_time;
-        if self.position.x < 0.0 {
-            self.position.x = 0.0;
-            self.velocity.x = -self.velocity.x; // Bounce off left wall
-            audio_system.play(wall_sound, self.velocity.dot(Vec2::X).abs() + 0.5);
-        } else if self.position.x > (1.0 - Self::RADIUS) {
-            self.position.x = 1.0 - Self::RADIUS;
-            self.velocity.x = -self.velocity.x; // Bounce off right wall
-            audio_system.play(wall_sound, self.velocity.dot(Vec2::X).abs() + 0.5);
+        if input.is_physical_key_just_pressed(KeyCode::ArrowDown) {
+            self.menu_selection = (self.menu_selection + 1) % count;
         }
-        if self.position.y < 0.0 {
-            self.position.y = 0.0;
-            self.velocity.y = -self.velocity.y; // Bounce off top wall
-        } else if self.position.y > (1.0 - Self::RADIUS) {
-            self.position.y = 1.0 - Self::RADIUS;
-            self.velocity.y = -self.velocity.y; // Bounce off bottom wall
+        if MenuItem::ALL[self.menu_selection] == MenuItem::Rebind {
+            let actions = Action::ALL.len();
+            if input.is_physical_key_just_pressed(KeyCode::ArrowLeft) {
+                self.rebind_selection = (self.rebind_selection + actions - 1) % actions;
+            }
+            if input.is_physical_key_just_pressed(KeyCode::ArrowRight) {
+                self.rebind_selection = (self.rebind_selection + 1) % actions;
+            }
         }
-        if Collision::do_spaces_collide(
-            &self.local_space(ortho_si),
-            &paddles.player_a.local_space(ortho_si, true),
-        )
-        .is_some()
+        if input.is_physical_key_just_pressed(KeyCode::Enter)
+            || input.is_physical_key_just_pressed(KeyCode::NumpadEnter)
         {
-            self.velocity.y = -self.velocity.y; // Bounce off player A paddle
-            let previous_velocity = self.velocity;
-            self.velocity.x += paddles.player_a.last_velocity * 2.0; // Add paddle velocity
-            self.velocity = self.velocity.normalize() * Ball::BALL_SPEED; // Normalize speed
-            self.position.y = PaddleState::PADDLE_HEIGHT;
-            audio_system.play(
-                bounce_sound,
-                previous_velocity.dot(self.velocity).abs() + 0.5,
-            );
-        } else if Collision::do_spaces_collide(
-            &self.local_space(ortho_si),
-            &paddles.player_b.local_space(ortho_si, false),
-        )
-        .is_some()
-        {
-            self.velocity.y = -self.velocity.y; // Bounce off player B paddle
-            let previous_velocity = self.velocity;
-            self.velocity.x += paddles.player_b.last_velocity * 2.0; // Add paddle velocity
-            self.velocity = self.velocity.normalize() * Ball::BALL_SPEED; // Normalize speed
-            self.position.y = 1.0 - PaddleState::PADDLE_HEIGHT - Ball::RADIUS;
-            audio_system.play(
-                bounce_sound,
-                previous_velocity.dot(self.velocity).abs() + 0.5,
+            match MenuItem::ALL[self.menu_selection] {
+                MenuItem::Start => self.reset(),
+                MenuItem::Opponent => self.cycle_ai_opponent(),
+                MenuItem::Rebind => self.controls.listen_for(Action::ALL[self.rebind_selection]),
+            }
+        }
+    }
+
+    // Keys that change the match rather than play it, paused or not
+    fn update_match_keys(&mut self, input: &InputSystem) {
+        if input.is_logical_key_just_pressed(Key::Character("i".into())) {
+            self.cycle_ai_opponent();
+        }
+
+        // Spawn an extra ball, fanning the directions out so they don't overlap
+        if input.is_logical_key_just_pressed(Key::Character("b".into())) {
+            let angle = self.balls.len() as f32 * 0.7;
+            self.spawn_ball(
+                Vec2::new(0.5, 0.5),
+                Vec2::from_angle(angle).rotate(Vec2::ONE),
             );
-        } else {
-            // Check if the ball is inside the goal area of either player
-            if Collision::do_spaces_collide(
-                &self.local_space(ortho_si),
-                &paddles.player_a.goal_local_space(ortho_si, true),
-            )
-            .is_some()
-            {
-                info!("Player B scores!");
-                self.position = Vec2::new(0.5, 0.5); // Reset ball position
-                self.velocity = Vec2::new(0.1, 0.1).normalize() * Ball::BALL_SPEED;
-            // Reset velocity
-            } else if Collision::do_spaces_collide(
-                &self.local_space(ortho_si),
-                &paddles.player_b.goal_local_space(ortho_si, false),
-            )
-            .is_some()
+        }
+    }
+
+    // Advances the paddles, balls and scores by one step
+    fn step_match(&mut self, input: &InputSystem, audio_system: &mut AudioSystem, delta_time: f32) {
+        self.paddles
+            .move_paddles(input, &self.controls, self.ai_player_b.is_some());
+        if let Some(ai) = &mut self.ai_player_b {
+            // Player B defends the bottom, so track the lowest ball
+            if let Some(ball) = self
+                .balls
+                .iter()
+                .max_by(|a, b| a.position.y.total_cmp(&b.position.y))
             {
-                info!("Player A scores!");
-                self.position = Vec2::new(0.5, 0.5); // Reset ball position
-                self.velocity = Vec2::new(0.1, 0.1).normalize() * Ball::BALL_SPEED;
-                // Reset velocity
+                ai.update(&mut self.paddles.player_b, ball, delta_time, &self.config);
+            }
+        }
+        self.paddles.update(delta_time, &self.config);
+
+        self.particles.update(delta_time);
+        self.obstacle_hits.clear();
+
+        if let MatchState::ServeCountdown { remaining } = &mut self.state {
+            // The served ball holds still at the center until the countdown ends
+            *remaining -= delta_time;
+            if *remaining > 0.0 {
+                return;
+            }
+            self.state = MatchState::Playing;
+        }
+
+        let mut index = 0;
+        while index < self.balls.len() {
+            let events = self.balls[index].update(
+                delta_time,
+                &mut self.paddles,
+                &self.obstacles,
+                &self.config,
+                &self.bouce_sound,
+                &self.wall_sound,
+                audio_system,
+            );
+            self.obstacle_hits.extend(events.obstacle_hits);
+            let scorer = events.scorer;
+            match scorer {
+                Some(Player::A) => self.score_a += 1,
+                Some(Player::B) => self.score_b += 1,
+                None => {}
+            }
+            if let Some(origin) = self.balls[index].bounced_at {
+                self.particles
+                    .emit(origin, Self::BOUNCE_PARTICLES, &EmitConfig::default());
+            }
+            // Extra balls leave play once they score, the last one is served
+            // again after a countdown
+            if scorer.is_some() && self.balls.len() > 1 {
+                self.balls.swap_remove(index);
+            } else {
+                if scorer.is_some() {
+                    self.balls[index] = Ball::serve(&mut self.rng, &self.config);
+                    if self.winner().is_some() {
+                        self.state = MatchState::GameOver;
+                        self.screen = Screen::GameOver;
+                    } else {
+                        self.state = MatchState::ServeCountdown {
+                            remaining: Self::SERVE_DELAY,
+                        };
+                    }
+                }
+                index += 1;
             }
         }
-    }
 
-    pub fn local_space(&self, ortho_si: &Transform) -> Transform {
-        let x = self.position.x;
-        let y = self.position.y;
+        if self.destroy_obstacles_on_hit {
+            // Highest first so the remaining indices stay valid, and once
+            // each when several balls hit the same obstacle
+            let mut hits = self.obstacle_hits.clone();
+            hits.sort_unstable();
+            hits.dedup();
+            for index in hits.into_iter().rev() {
+                self.obstacles.remove(index);
+            }
+        }
+    }
 
-        ortho_si
-            .translate(Vec3::new(x, y, 0.0))
-            .scale(Vec3::splat(Self::RADIUS))
+    // Places text of `len` characters horizontally centered at height `y`
+    fn centered_text_space(&self, t: &Transform, len: usize, y: f32) -> Transform {
+        let (width, height) = Self::target_size();
+        let glyph_width =
+            Self::TEXT_HEIGHT * self.font.glyph_aspect() * height as f32 / width as f32;
+        let x = 0.5 - glyph_width * len as f32 * 0.5;
+        t.translate(Vec3::new(x, y - Self::TEXT_HEIGHT * 0.5, 0.0))
+            .scale(Vec3::new(glyph_width, Self::TEXT_HEIGHT, 1.0))
     }
-}
 
-impl Default for Ball {
-    fn default() -> Self {
-        Self {
-            position: Vec2::new(0.5, 0.5),
-            velocity: Vec2::new(0.1, 0.1).normalize() * Ball::BALL_SPEED, // Initial velocity
+    pub fn render(&self, drawer: &mut Drawer) {
+        drawer.clear(self.background_color);
+
+        let t = &Transform::ortographic_size_invariant();
+
+        if self.screen != Screen::Menu {
+            self.render_court(drawer, t);
+        }
+
+        if let Some(action) = self.controls.listening() {
+            self.render_message(drawer, t, "PICK A KEY FOR", 0.4);
+            self.render_message(drawer, t, action.label(), 0.55);
+        } else {
+            match self.screen {
+                Screen::Menu => self.render_menu(drawer, t),
+                Screen::Playing => {
+                    if let MatchState::ServeCountdown { remaining } = self.state {
+                        let countdown = format!("{}", remaining.ceil().max(1.0) as u32);
+                        self.render_message(drawer, t, &countdown, 0.4);
+                    }
+                }
+                Screen::Paused => self.render_message(drawer, t, "PAUSED", 0.5),
+                Screen::GameOver => {
+                    let message = match self.winner() {
+                        Some(Player::A) => "PLAYER A WINS",
+                        Some(Player::B) => "PLAYER B WINS",
+                        None => "GAME OVER",
+                    };
+                    self.render_message(drawer, t, message, 0.4);
+                    self.render_message(drawer, t, "PRESS SPACE", 0.65);
+                }
+            }
+        }
+
+        if self.debug_overlay {
+            self.render_debug_overlay(drawer, t);
+        }
+
+        // Over everything else, like the OS cursor it replaces
+        if let Some(cursor) = self.cursor {
+            let (width, height) = Self::target_size();
+            let cursor_width = Self::CURSOR_SIZE * height as f32 / width as f32;
+            let cursor_space = t
+                .translate(Vec3::new(cursor.x, cursor.y, 0.0))
+                .scale(Vec3::new(cursor_width, Self::CURSOR_SIZE, 1.0))
+                .translate(Vec3::new(-0.5, -0.5, 0.0));
+            drawer.draw_circle_slow(Some(&cursor_space), Some(&EngineColor::WHITE));
         }
     }
-}
 
-pub struct Game {
-    paddles: DualPaddleState,
-    ball: Ball,
-    bouce_sound: AudioHandle,
-    wall_sound: AudioHandle,
-}
+    // The paddles, balls and scores, under every screen but the menu
+    fn render_court(&self, drawer: &mut Drawer, t: &Transform) {
+        let (player_a_space, player_b_space) = self.paddles.local_spaces(t, &self.config);
+        let player_a_color = self.paddles.player_a.color(EngineColor::RED);
+        let player_b_color = self.paddles.player_b.color(EngineColor::BLUE);
+        drawer.draw_square_batched(Some(&player_a_space), Some(&player_a_color));
+        drawer.draw_square_batched(Some(&player_b_space), Some(&player_b_color));
+        for obstacle in &self.obstacles {
+            drawer.draw_square_batched(Some(&t.compose(obstacle)), Some(&EngineColor::GREEN));
+        }
 
-impl Game {
-    pub fn target_size() -> (u32, u32) {
-        (320, 240)
+        for ball in &self.balls {
+            let ball_space = ball.local_space(t, &self.config);
+            drawer.draw_circle_slow(Some(&ball_space), Some(&EngineColor::WHITE));
+        }
+
+        self.particles.render(drawer, t);
+
+        // Each score sits on its player's side of the court
+        let score_a = self.score_a.to_string();
+        let score_a_space = self.centered_text_space(t, score_a.len(), 0.25);
+        drawer.draw_text(
+            &score_a,
+            &self.font,
+            Some(&score_a_space),
+            Some(&EngineColor::RED),
+        );
+        let score_b = self.score_b.to_string();
+        let score_b_space = self.centered_text_space(t, score_b.len(), 0.75);
+        drawer.draw_text(
+            &score_b,
+            &self.font,
+            Some(&score_b_space),
+            Some(&EngineColor::BLUE),
+        );
     }
 
-    pub fn init(rendering_system: &mut RenderingSystem, audio_system: &mut AudioSystem) -> Self {
-        Self {
-            paddles: DualPaddleState::default(),
-            ball: Ball::default(),
-            bouce_sound: audio_system.load_buffer(include_bytes!("assets/bounce_1.wav")),
-            wall_sound: audio_system.load_buffer(include_bytes!("assets/wall_1.wav")),
+    fn render_menu(&self, drawer: &mut Drawer, t: &Transform) {
+        self.render_message(drawer, t, "PONG", 0.25);
+        for (index, item) in MenuItem::ALL.into_iter().enumerate() {
+            let label = match item {
+                MenuItem::Start => "START".to_string(),
+                MenuItem::Opponent => {
+                    let opponent = match self.ai_opponent() {
+                        None => "HUMAN",
+                        Some(Difficulty::Easy) => "EASY",
+                        Some(Difficulty::Normal) => "NORMAL",
+                        Some(Difficulty::Hard) => "HARD",
+                    };
+                    format!("OPPONENT {}", opponent)
+                }
+                MenuItem::Rebind => {
+                    format!("REBIND {}", Action::ALL[self.rebind_selection].label())
+                }
+            };
+            let line = if index == self.menu_selection {
+                format!("> {} <", label)
+            } else {
+                label
+            };
+            self.render_message(drawer, t, &line, 0.5 + index as f32 * 0.12);
         }
     }
 
-    pub fn update(&mut self, input: &InputSystem, audio_system: &mut AudioSystem, delta_time: f32) {
-        self.paddles.move_paddles(input, delta_time);
-        self.ball.update(
-            delta_time,
-            &self.paddles,
-            &Transform::ortographic_size_invariant(),
-            &self.bouce_sound,
-            &self.wall_sound,
-            audio_system,
+    // A line of white text centered at height `y`
+    fn render_message(&self, drawer: &mut Drawer, t: &Transform, message: &str, y: f32) {
+        let message_space = self.centered_text_space(t, message.len(), y);
+        drawer.draw_text(
+            message,
+            &self.font,
+            Some(&message_space),
+            Some(&EngineColor::WHITE),
         );
     }
 
-    pub fn render(&self, drawer: &mut Drawer) {
-        drawer.clear_slow(Color::BLACK);
-
-        let t = &Transform::ortographic_size_invariant();
+    fn render_debug_overlay(&self, drawer: &mut Drawer, t: &Transform) {
+        const GRID_LINES: usize = 10;
+        let grid_color = EngineColor {
+            a: 0.15,
+            ..EngineColor::WHITE
+        };
+        for i in 0..=GRID_LINES {
+            let offset = i as f32 / GRID_LINES as f32;
+            let thickness = Drawer::DEBUG_LINE_THICKNESS * 0.5;
+            drawer.draw_line(
+                Vec2::new(offset, 0.0),
+                Vec2::new(offset, 1.0),
+                grid_color,
+                thickness,
+            );
+            drawer.draw_line(
+                Vec2::new(0.0, offset),
+                Vec2::new(1.0, offset),
+                grid_color,
+                thickness,
+            );
+        }
 
-        let (player_a_space, player_b_space) = self.paddles.local_spaces(t);
-        drawer.draw_square_slow(Some(&player_a_space), Some(&EngineColor::RED));
-        drawer.draw_square_slow(Some(&player_b_space), Some(&EngineColor::BLUE));
+        // Where each ball will be in a quarter of a second, if nothing is hit
+        for ball in &self.balls {
+            let center = ball.center(&self.config);
+            drawer.draw_line(
+                center,
+                center + ball.velocity * 0.25,
+                EngineColor::GREEN,
+                Drawer::DEBUG_LINE_THICKNESS,
+            );
+        }
 
-        let ball_space = self.ball.local_space(t);
-        drawer.draw_square_slow(Some(&ball_space), Some(&EngineColor::WHITE));
+        let goal_a = self
+            .paddles
+            .player_a
+            .goal_local_space(t, true, &self.config);
+        let goal_b = self
+            .paddles
+            .player_b
+            .goal_local_space(t, false, &self.config);
+        drawer.draw_wireframe_square(&goal_a, EngineColor::RED);
+        drawer.draw_wireframe_square(&goal_b, EngineColor::BLUE);
     }
 }
@@ -1,64 +1,80 @@
-use glam::Vec3;
+use glam::{Mat4, Quat, Vec2, Vec3};
+#[cfg(feature = "gpu")]
 use wgpu::{Buffer, Queue};
 
+// Only the matrix is stored; the column-major floats the GPU wants are read
+// straight out of it when the transform is uploaded.
+#[derive(Debug, Clone)]
 pub struct Transform {
     matrix: glam::Mat4,
-    raw: [[f32; 4]; 4],
 }
 
 impl Transform {
     pub fn new() -> Self {
-        let mat = glam::Mat4::IDENTITY;
-        Self {
-            matrix: mat,
-            raw: mat.to_cols_array_2d(),
-        }
+        Self::from_matrix(glam::Mat4::IDENTITY)
     }
 
     pub fn from_matrix(matrix: glam::Mat4) -> Self {
-        let raw = matrix.to_cols_array_2d();
-        Self { matrix, raw }
+        Self { matrix }
+    }
+
+    /// Builds translate * rotate (around Z) * scale in one go, equivalent to
+    /// `Transform::new().translate(position).rotate(rotation, Vec3::Z).scale(scale)`.
+    pub fn from_trs(position: Vec3, rotation_radians: f32, scale: Vec3) -> Self {
+        Self::from_matrix(glam::Mat4::from_scale_rotation_translation(
+            scale,
+            glam::Quat::from_rotation_z(rotation_radians),
+            position,
+        ))
     }
 
     pub fn translate(&self, translation: Vec3) -> Self {
-        let mat = self.matrix * glam::Mat4::from_translation(translation);
-        Self {
-            matrix: mat,
-            raw: mat.to_cols_array_2d(),
-        }
+        Self::from_matrix(self.matrix * glam::Mat4::from_translation(translation))
     }
 
     pub fn rotate(&self, angle: f32, axis: Vec3) -> Self {
-        let mat = self.matrix * glam::Mat4::from_axis_angle(axis, angle);
-        Self {
-            matrix: mat,
-            raw: mat.to_cols_array_2d(),
-        }
+        Self::from_matrix(self.matrix * glam::Mat4::from_axis_angle(axis, angle))
+    }
+
+    /// Like `rotate`, but about `pivot` (in local units) instead of the local
+    /// origin. `rotate_around(angle, Vec3::Z, Vec3::new(0.5, 0.5, 0.0))` spins
+    /// the unit square about its center.
+    pub fn rotate_around(&self, angle: f32, axis: Vec3, pivot: Vec3) -> Self {
+        self.translate(pivot).rotate(angle, axis).translate(-pivot)
     }
 
     pub fn scale(&self, scale: Vec3) -> Self {
-        let mat = self.matrix * glam::Mat4::from_scale(scale);
-        Self {
-            matrix: mat,
-            raw: mat.to_cols_array_2d(),
-        }
+        Self::from_matrix(self.matrix * glam::Mat4::from_scale(scale))
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        self.matrix
+    }
+
+    /// Returns `self * other`, i.e. `other` is applied first.
+    pub fn compose(&self, other: &Self) -> Self {
+        Self::from_matrix(self.matrix * other.matrix)
+    }
+
+    pub fn as_raw(&self) -> [[f32; 4]; 4] {
+        self.matrix.to_cols_array_2d()
     }
 
     pub fn as_bytes(&self) -> &[u8] {
-        bytemuck::cast_slice(&self.raw)
+        let columns: &[f32; 16] = self.matrix.as_ref();
+        bytemuck::cast_slice(columns)
     }
 
+    #[cfg(feature = "gpu")]
     pub fn write_buffer(&self, buffer: &Buffer, queue: &Queue) {
         queue.write_buffer(buffer, 0, self.as_bytes());
     }
 
     pub fn ortographic_size_invariant() -> Self {
         // Creates a size invariant orthographic transform
-        let mat = glam::Mat4::orthographic_rh(0.0, 1.0, 1.0, 0.0, -100.0, 100.0);
-        Self {
-            matrix: mat,
-            raw: mat.to_cols_array_2d(),
-        }
+        Self::from_matrix(glam::Mat4::orthographic_rh(
+            0.0, 1.0, 1.0, 0.0, -100.0, 100.0,
+        ))
     }
 
     pub fn project(&self, point: Vec3) -> Vec3 {
@@ -66,11 +82,247 @@ impl Transform {
         (self.matrix * point.extend(1.0)).truncate()
     }
 
+    /// Whether `point`, in the space this transform maps into, lies inside the
+    /// transformed unit square.
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        let local = self.matrix.inverse().transform_point3(point);
+        (0.0..=1.0).contains(&local.x) && (0.0..=1.0).contains(&local.y)
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self::from_matrix(self.matrix.inverse())
+    }
+
+    /// How much the transform scales volumes. It is negative when an odd
+    /// number of axes are flipped, as when a sprite is mirrored with a
+    /// negative scale, and zero when the transform squashes space flat.
+    pub fn determinant(&self) -> f32 {
+        self.matrix.determinant()
+    }
+
+    /// Whether the transform flips space inside out. Triangles drawn through
+    /// a mirrored transform wind the other way, so back-face culling would
+    /// throw them away. `Drawer` draws them double-sided instead.
+    pub fn is_mirrored(&self) -> bool {
+        self.determinant() < 0.0
+    }
+
+    /// Splits the transform into translation, rotation and scale. Only exact
+    /// for affine transforms without shear (projections don't decompose).
+    pub fn decompose(&self) -> (Vec3, Quat, Vec3) {
+        let (scale, rotation, translation) = self.matrix.to_scale_rotation_translation();
+        (translation, rotation, scale)
+    }
+
+    /// Interpolates between two transforms by lerping their translation and
+    /// scale and slerping their rotation.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let (from_translation, from_rotation, from_scale) = self.decompose();
+        let (to_translation, to_rotation, to_scale) = other.decompose();
+        Self::from_matrix(Mat4::from_scale_rotation_translation(
+            from_scale.lerp(to_scale, t),
+            from_rotation.slerp(to_rotation, t),
+            from_translation.lerp(to_translation, t),
+        ))
+    }
+
     pub fn map_towards(&self, other: &Self) -> Self {
-        let mat = other.matrix.inverse() * self.matrix;
+        Self::from_matrix(other.matrix.inverse() * self.matrix)
+    }
+}
+
+/// A 2D camera looking at the game's 0..1 size-invariant space.
+#[derive(Debug, Clone, Copy)]
+pub struct Camera2D {
+    // Point of the 0..1 space shown at the middle of the screen
+    pub center: Vec2,
+    // Values above 1 zoom in
+    pub zoom: f32,
+    // Radians, rotating the view around `center`
+    pub rotation: f32,
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
         Self {
-            matrix: mat,
-            raw: mat.to_cols_array_2d(),
+            center: Vec2::new(0.5, 0.5),
+            zoom: 1.0,
+            rotation: 0.0,
         }
     }
 }
+
+impl Camera2D {
+    /// Builds the view matrix for a viewport measured in `viewport` units
+    /// (e.g. pixels). Working in those units rather than 0..1 keeps rotation
+    /// free of aspect-ratio distortion.
+    pub fn view_matrix(&self, viewport: Vec2) -> Mat4 {
+        Mat4::from_translation((viewport * 0.5).extend(0.0))
+            * Mat4::from_rotation_z(-self.rotation)
+            * Mat4::from_scale(Vec3::new(self.zoom, self.zoom, 1.0))
+            * Mat4::from_translation((-self.center * viewport).extend(0.0))
+    }
+
+    /// The view matrix conjugated with `projection`, so it can be applied in
+    /// clip space on top of transforms that are already projected.
+    pub fn clip_matrix(&self, viewport: Vec2, projection: &Transform) -> Mat4 {
+        let projection = projection.matrix();
+        projection * self.view_matrix(viewport) * projection.inverse()
+    }
+}
+
+/// Maps `position` inside `content_rect` (`(offset, size)`, in the same pixels
+/// as the position) to clip space, then back through `clip_from_world`.
+pub fn screen_to_world(position: Vec2, content_rect: (Vec2, Vec2), clip_from_world: Mat4) -> Vec3 {
+    let (offset, size) = content_rect;
+    let uv = (position - offset) / size;
+    let clip = Vec3::new(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0);
+    clip_from_world.inverse().transform_point3(clip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{a} != {b}");
+    }
+
+    #[test]
+    fn contains_point_uses_the_transformed_square() {
+        let space = Transform::from_trs(Vec3::new(1.0, 1.0, 0.0), 0.0, Vec3::splat(2.0));
+
+        assert!(space.contains_point(Vec3::new(2.0, 2.0, 0.0)));
+        assert!(!space.contains_point(Vec3::new(0.5, 2.0, 0.0)));
+    }
+
+    #[test]
+    fn screen_to_world_undoes_the_letterbox_and_camera() {
+        // A 320x240 game letterboxed into an 800x480 window, scaled 2x
+        let content_rect = (Vec2::new(80.0, 0.0), Vec2::new(640.0, 480.0));
+        let ortho = Transform::ortographic_size_invariant();
+        // Only x and y matter, z is wherever clip depth 0 lands
+        let world = |position: Vec2, clip_from_world: Mat4| {
+            let world = screen_to_world(position, content_rect, clip_from_world);
+            world.truncate().extend(0.0)
+        };
+
+        assert_close(world(Vec2::new(80.0, 0.0), ortho.matrix()), Vec3::ZERO);
+        assert_close(
+            world(Vec2::new(400.0, 240.0), ortho.matrix()),
+            Vec3::new(0.5, 0.5, 0.0),
+        );
+        assert_close(
+            world(Vec2::new(720.0, 360.0), ortho.matrix()),
+            Vec3::new(1.0, 0.75, 0.0),
+        );
+
+        // Zoomed in 2x on the center, the window corner only reaches a quarter in
+        let camera = Camera2D {
+            zoom: 2.0,
+            ..Camera2D::default()
+        };
+        let pixels =
+            Transform::from_matrix(Mat4::orthographic_rh(0.0, 320.0, 240.0, 0.0, -100.0, 100.0));
+        let zoomed = camera.clip_matrix(Vec2::new(320.0, 240.0), &pixels) * ortho.matrix();
+        assert_close(
+            world(Vec2::new(80.0, 0.0), zoomed),
+            Vec3::new(0.25, 0.25, 0.0),
+        );
+        assert_close(
+            world(Vec2::new(400.0, 240.0), zoomed),
+            Vec3::new(0.5, 0.5, 0.0),
+        );
+    }
+
+    #[test]
+    fn from_trs_matches_the_builder_chain() {
+        let position = Vec3::new(3.0, -2.0, 0.0);
+        let scale = Vec3::new(2.0, 4.0, 1.0);
+        let built = Transform::new()
+            .translate(position)
+            .rotate(0.3, Vec3::Z)
+            .scale(scale);
+
+        assert!(Transform::from_trs(position, 0.3, scale)
+            .matrix()
+            .abs_diff_eq(built.matrix(), 1e-5));
+    }
+
+    #[test]
+    fn decompose_returns_the_trs_parts() {
+        let position = Vec3::new(3.0, -2.0, 0.0);
+        let scale = Vec3::new(2.0, 4.0, 1.0);
+        let (translation, rotation, decomposed_scale) =
+            Transform::from_trs(position, 0.3, scale).decompose();
+
+        assert_close(translation, position);
+        assert_close(decomposed_scale, scale);
+        assert!(rotation.abs_diff_eq(Quat::from_rotation_z(0.3), 1e-5));
+    }
+
+    #[test]
+    fn decompose_round_trips_through_from_trs() {
+        let matrix =
+            Transform::from_trs(Vec3::new(-1.5, 0.25, 2.0), -2.1, Vec3::new(0.5, 3.0, 1.0));
+        let (translation, rotation, scale) = matrix.decompose();
+        let (axis, angle) = rotation.to_axis_angle();
+        let rebuilt = Transform::from_trs(translation, angle * axis.z, scale);
+
+        assert!(rebuilt.matrix().abs_diff_eq(matrix.matrix(), 1e-5));
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let matrix = Transform::from_trs(Vec3::new(4.0, -3.0, 1.0), 0.7, Vec3::new(2.0, 0.5, 1.0));
+
+        assert!(matrix
+            .inverse()
+            .compose(&matrix)
+            .matrix()
+            .abs_diff_eq(Mat4::IDENTITY, 1e-5));
+    }
+
+    #[test]
+    fn lerp_halfway_between_transforms() {
+        let from = Transform::from_trs(Vec3::ZERO, 0.0, Vec3::ONE);
+        let to = Transform::from_trs(Vec3::new(2.0, 4.0, 0.0), 0.0, Vec3::splat(3.0));
+        let (translation, _, scale) = from.lerp(&to, 0.5).decompose();
+
+        assert_close(translation, Vec3::new(1.0, 2.0, 0.0));
+        assert_close(scale, Vec3::splat(2.0));
+    }
+
+    #[test]
+    fn rotate_around_center_keeps_the_unit_square_in_place() {
+        let pivot = Vec3::new(0.5, 0.5, 0.0);
+        let spun = Transform::new().rotate_around(std::f32::consts::FRAC_PI_2, Vec3::Z, pivot);
+
+        assert_close(spun.project(pivot), pivot);
+        // A quarter turn counterclockwise sends each corner to the next one
+        assert_close(spun.project(Vec3::ZERO), Vec3::new(1.0, 0.0, 0.0));
+        assert_close(
+            spun.project(Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(1.0, 1.0, 0.0),
+        );
+        assert_close(
+            spun.project(Vec3::new(1.0, 1.0, 0.0)),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        assert_close(spun.project(Vec3::new(0.0, 1.0, 0.0)), Vec3::ZERO);
+    }
+
+    #[test]
+    fn flipping_one_axis_mirrors_the_transform() {
+        let sprite = Transform::ortographic_size_invariant().scale(Vec3::splat(0.25));
+        assert!(!sprite.is_mirrored());
+
+        let flipped = sprite.scale(Vec3::new(-1.0, 1.0, 1.0));
+        assert!(flipped.is_mirrored());
+        assert!((flipped.determinant() + sprite.determinant()).abs() < 1e-6);
+
+        // Flipping back, or flipping both axes, is just a rotation
+        assert!(!flipped.scale(Vec3::new(-1.0, 1.0, 1.0)).is_mirrored());
+        assert!(!sprite.scale(Vec3::new(-1.0, -1.0, 1.0)).is_mirrored());
+    }
+}
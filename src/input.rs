@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, Touch, TouchPhase};
+use winit::keyboard::{Key, KeyCode};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TouchPoint {
+    id: u64,
+    // Physical pixels, like InputSystem::mouse_position
+    position: (f64, f64),
+    phase: TouchPhase,
+}
+
+// A press or release, see InputSystem::events
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum InputEvent {
+    Mouse(MouseButton, ElementState),
+    PhysicalKey(KeyCode, ElementState),
+    // Normalized, see InputSystem::normalize_logical_key
+    LogicalKey(Key, ElementState),
+}
+
+// One fixed step of input, see app::start_recording. Only changes are
+// kept: held keys follow from replaying the presses and releases in order,
+// and the rest is only stored on steps where it isn't zero or unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct InputFrame {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    events: Vec<InputEvent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mouse_position: Option<(f64, f64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mouse_delta: Option<(f64, f64)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    scroll_delta: Option<(f32, f32)>,
+}
+
+/// A match's input, one frame per fixed step, and the seed it was served
+/// with. Replaying it with `app::load_replay` plays the match out again
+/// step for step, as long as the game settings (AI opponent, controls,
+/// bounce response) are the same. Touches aren't recorded.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct InputRecording {
+    seed: u64,
+    frames: Vec<InputFrame>,
+}
+
+impl InputRecording {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            frames: Vec::new(),
+        }
+    }
+
+    // Adds the step `input` is showing
+    pub(crate) fn record(&mut self, input: &InputSystem) {
+        let mut frame = input.frame();
+        // The replay starts without a position to move from
+        if self.frames.is_empty() {
+            frame.mouse_position = Some(input.mouse_position);
+        }
+        self.frames.push(frame);
+    }
+
+    pub(crate) fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Fixed steps recorded, FIXED_TIMESTEP seconds each
+    pub(crate) fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        // Keys and numbers only, which always serialize
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    // None, with the reason logged, if `json` isn't a recording
+    pub(crate) fn from_json(json: &str) -> Option<Self> {
+        serde_json::from_str(json)
+            .map_err(|err| log::error!("Failed to read the replay: {}", err))
+            .ok()
+    }
+}
+
+// A recording being played back, see app::load_replay
+pub(crate) struct Replay {
+    recording: InputRecording,
+    // Index of the frame the next step plays
+    next: usize,
+    // Rebuilt from the frames, the game reads this instead of the live input
+    input: InputSystem,
+}
+
+impl Replay {
+    pub(crate) fn new(recording: InputRecording) -> Self {
+        Self {
+            recording,
+            next: 0,
+            input: InputSystem::default(),
+        }
+    }
+
+    // Plays the next recorded step, or returns None once they've all played
+    pub(crate) fn next_input(&mut self, live: &InputSystem) -> Option<&InputSystem> {
+        let frame = self.recording.frames.get(self.next)?;
+        self.next += 1;
+        self.input.end_frame();
+        self.input.replay_frame(frame, live);
+        Some(&self.input)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct InputSystem {
+    pub(crate) mouse_position: (f64, f64),
+    // Where the mouse was at the last end_frame, to record only moves
+    previous_mouse_position: (f64, f64),
+    // Where the game sits in the window, from RenderingSystem::content_rect
+    pub(crate) content_rect: (Vec2, Vec2),
+    mouse_buttons: HashMap<MouseButton, ElementState>,
+    // Keys by position on the keyboard, the same on every layout. Use these
+    // for movement, where the shape of the keys matters (WASD, arrows).
+    physical_key_states: HashMap<KeyCode, ElementState>,
+    // Keys by what they type on the user's layout. Use these for shortcuts
+    // named after a letter, so "P" for pause is P on AZERTY and Dvorak too.
+    // Characters are stored lowercased, see normalize_logical_key.
+    logical_key_states: HashMap<Key, ElementState>,
+    // Scrolled lines since the last frame, see scroll_delta
+    scroll_delta: (f32, f32),
+    // Raw motion in physical pixels since the last frame, only while locked
+    pub(crate) mouse_delta: (f64, f64),
+    pub(crate) pointer_locked: bool,
+    // The OS cursor is hidden and the game draws its own, see WebApp::set_custom_cursor
+    pub(crate) custom_cursor: bool,
+    touches: Vec<TouchPoint>,
+    // Presses and releases since the last end_frame, in the order they came.
+    // A tap that starts and ends between two frames only shows up here.
+    events: Vec<InputEvent>,
+
+    // Snapshots from the end of the previous frame, for edge detection
+    previous_mouse_buttons: HashMap<MouseButton, ElementState>,
+    previous_physical_key_states: HashMap<KeyCode, ElementState>,
+    previous_logical_key_states: HashMap<Key, ElementState>,
+}
+
+impl InputSystem {
+    // Browsers report pixel deltas for most wheels and touchpads, roughly this many per line
+    const PIXELS_PER_LINE: f32 = 40.0;
+
+    pub(crate) fn is_mouse_down(&self, button: MouseButton) -> bool {
+        matches!(self.mouse_buttons.get(&button), Some(ElementState::Pressed))
+    }
+    pub(crate) fn is_mouse_up(&self, button: MouseButton) -> bool {
+        match self.mouse_buttons.get(&button) {
+            Some(ElementState::Pressed) => false,
+            Some(ElementState::Released) => true,
+            None => false,
+        }
+    }
+    pub(crate) fn is_physical_key_down(&self, key: KeyCode) -> bool {
+        matches!(
+            self.physical_key_states.get(&key),
+            Some(ElementState::Pressed)
+        )
+    }
+    pub(crate) fn is_physical_key_up(&self, key: KeyCode) -> bool {
+        match self.physical_key_states.get(&key) {
+            Some(ElementState::Pressed) => false,
+            Some(ElementState::Released) => true,
+            None => false,
+        }
+    }
+
+    pub(crate) fn was_mouse_down(&self, button: MouseButton) -> bool {
+        matches!(
+            self.previous_mouse_buttons.get(&button),
+            Some(ElementState::Pressed)
+        )
+    }
+    pub(crate) fn was_physical_key_down(&self, key: KeyCode) -> bool {
+        matches!(
+            self.previous_physical_key_states.get(&key),
+            Some(ElementState::Pressed)
+        )
+    }
+
+    // True only on the frame the button went down, even if it was let go
+    // again before the frame ended, so quick taps aren't lost
+    pub(crate) fn is_mouse_just_pressed(&self, button: MouseButton) -> bool {
+        self.events
+            .contains(&InputEvent::Mouse(button, ElementState::Pressed))
+    }
+    pub(crate) fn is_mouse_just_released(&self, button: MouseButton) -> bool {
+        self.events
+            .contains(&InputEvent::Mouse(button, ElementState::Released))
+    }
+    pub(crate) fn is_physical_key_just_pressed(&self, key: KeyCode) -> bool {
+        self.events
+            .contains(&InputEvent::PhysicalKey(key, ElementState::Pressed))
+    }
+    pub(crate) fn is_physical_key_just_released(&self, key: KeyCode) -> bool {
+        self.events
+            .contains(&InputEvent::PhysicalKey(key, ElementState::Released))
+    }
+    // Every physical key that went down this frame, in the order they were pressed
+    pub(crate) fn physical_keys_just_pressed(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.events.iter().filter_map(|event| match event {
+            InputEvent::PhysicalKey(key, ElementState::Pressed) => Some(*key),
+            _ => None,
+        })
+    }
+
+    // Every press and release since the last frame, oldest first
+    pub(crate) fn events(&self) -> &[InputEvent] {
+        &self.events
+    }
+
+    pub(crate) fn record(&mut self, event: InputEvent) {
+        match &event {
+            InputEvent::Mouse(button, state) => {
+                self.mouse_buttons.insert(*button, *state);
+            }
+            InputEvent::PhysicalKey(key, state) => {
+                self.physical_key_states.insert(*key, *state);
+            }
+            InputEvent::LogicalKey(key, state) => {
+                self.logical_key_states.insert(key.clone(), *state);
+            }
+        }
+        self.events.push(event);
+    }
+
+    // This step's input for a recording, see InputFrame
+    fn frame(&self) -> InputFrame {
+        let moved = self.mouse_position != self.previous_mouse_position;
+        InputFrame {
+            events: self.events.clone(),
+            mouse_position: moved.then_some(self.mouse_position),
+            mouse_delta: (self.mouse_delta != (0.0, 0.0)).then_some(self.mouse_delta),
+            scroll_delta: (self.scroll_delta != (0.0, 0.0)).then_some(self.scroll_delta),
+        }
+    }
+
+    // Applies a recorded step as if it had just happened. Where the game sits
+    // on the page and the cursor mode come from `live`, they depend on the
+    // page rather than the match.
+    fn replay_frame(&mut self, frame: &InputFrame, live: &InputSystem) {
+        for event in &frame.events {
+            self.record(event.clone());
+        }
+        if let Some(position) = frame.mouse_position {
+            self.mouse_position = position;
+        }
+        self.mouse_delta = frame.mouse_delta.unwrap_or_default();
+        self.scroll_delta = frame.scroll_delta.unwrap_or_default();
+        self.content_rect = live.content_rect;
+        self.pointer_locked = live.pointer_locked;
+        self.custom_cursor = live.custom_cursor;
+    }
+
+    // Shift changes the character a key reports ("p" vs "P"), and it may be
+    // pressed or released in between the press and release of the key itself,
+    // so characters are compared without case.
+    pub(crate) fn normalize_logical_key(key: Key) -> Key {
+        match key {
+            Key::Character(c) => Key::Character(c.to_lowercase().into()),
+            other => other,
+        }
+    }
+
+    pub(crate) fn is_logical_key_down(&self, key: Key) -> bool {
+        matches!(
+            self.logical_key_states
+                .get(&Self::normalize_logical_key(key)),
+            Some(ElementState::Pressed)
+        )
+    }
+    pub(crate) fn was_logical_key_down(&self, key: Key) -> bool {
+        matches!(
+            self.previous_logical_key_states
+                .get(&Self::normalize_logical_key(key)),
+            Some(ElementState::Pressed)
+        )
+    }
+    pub(crate) fn is_logical_key_just_pressed(&self, key: Key) -> bool {
+        self.events.contains(&InputEvent::LogicalKey(
+            Self::normalize_logical_key(key),
+            ElementState::Pressed,
+        ))
+    }
+
+    // The cursor in the 0..1 space of Transform::ortographic_size_invariant,
+    // with y pointing down. None while the cursor is over the letterbox bars.
+    pub(crate) fn normalized_mouse_position(&self) -> Option<Vec2> {
+        let (offset, size) = self.content_rect;
+        let position = Vec2::new(self.mouse_position.0 as f32, self.mouse_position.1 as f32);
+        let normalized = (position - offset) / size;
+        let inside = (0.0..=1.0).contains(&normalized.x) && (0.0..=1.0).contains(&normalized.y);
+        inside.then_some(normalized)
+    }
+
+    // How far the mouse moved this frame, in physical pixels, while the
+    // pointer is locked to the canvas. Unlike mouse_position it isn't stopped
+    // by the edges of the window. Always zero without a lock.
+    pub(crate) fn mouse_delta(&self) -> Vec2 {
+        Vec2::new(self.mouse_delta.0 as f32, self.mouse_delta.1 as f32)
+    }
+
+    // Whether the canvas has the pointer, see WebApp::set_pointer_lock_on_click
+    pub(crate) fn is_pointer_locked(&self) -> bool {
+        self.pointer_locked
+    }
+
+    // Where the game should draw its own cursor, like normalized_mouse_position.
+    // None unless the OS cursor is hidden over the canvas.
+    pub(crate) fn custom_cursor_position(&self) -> Option<Vec2> {
+        self.custom_cursor
+            .then(|| self.normalized_mouse_position())
+            .flatten()
+    }
+
+    // Scroll accumulated this frame, in lines. Pixel deltas are converted with
+    // PIXELS_PER_LINE. Positive y is scrolling up (away from the user), and
+    // positive x is scrolling right, as winit reports them.
+    pub(crate) fn scroll_delta(&self) -> (f32, f32) {
+        self.scroll_delta
+    }
+
+    // Active touches. A touch that ended or was cancelled is still reported,
+    // with that phase, until the end of the frame.
+    pub(crate) fn touches(&self) -> &[TouchPoint] {
+        &self.touches
+    }
+
+    pub(crate) fn update_touch(&mut self, touch: &Touch) {
+        let point = TouchPoint {
+            id: touch.id,
+            position: (touch.location.x, touch.location.y),
+            phase: touch.phase,
+        };
+        match self.touches.iter_mut().find(|t| t.id == touch.id) {
+            Some(existing) => *existing = point,
+            None => self.touches.push(point),
+        }
+    }
+
+    pub(crate) fn add_scroll(&mut self, delta: MouseScrollDelta) {
+        let (x, y) = match delta {
+            MouseScrollDelta::LineDelta(x, y) => (x, y),
+            MouseScrollDelta::PixelDelta(position) => (
+                position.x as f32 / Self::PIXELS_PER_LINE,
+                position.y as f32 / Self::PIXELS_PER_LINE,
+            ),
+        };
+        self.scroll_delta.0 += x;
+        self.scroll_delta.1 += y;
+    }
+
+    // Must be called once per frame after the game has consumed the input
+    pub(crate) fn end_frame(&mut self) {
+        self.scroll_delta = (0.0, 0.0);
+        self.mouse_delta = (0.0, 0.0);
+        self.events.clear();
+        self.previous_mouse_position = self.mouse_position;
+        self.touches
+            .retain(|t| !matches!(t.phase, TouchPhase::Ended | TouchPhase::Cancelled));
+        self.previous_mouse_buttons.clone_from(&self.mouse_buttons);
+        self.previous_physical_key_states
+            .clone_from(&self.physical_key_states);
+        self.previous_logical_key_states
+            .clone_from(&self.logical_key_states);
+    }
+}
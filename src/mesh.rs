@@ -0,0 +1,225 @@
+use glam::Vec2;
+
+use crate::color::EngineColor;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    // Multiplied with the draw's color, see Drawer::draw_gradient_square
+    pub color: [f32; 4],
+}
+
+#[cfg(feature = "gpu")]
+impl Vertex {
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+/// A vertex for the textured pipeline. The flat pipeline keeps using `Vertex`.
+pub struct TexturedVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+#[cfg(feature = "gpu")]
+impl TexturedVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x2,
+    ];
+
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TexturedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+/// Per-instance data for batched square draws: the full model-view-projection
+/// matrix followed by the uniform color.
+pub struct InstanceRaw {
+    pub model: [[f32; 4]; 4],
+    pub color: EngineColor,
+}
+
+impl InstanceRaw {
+    #[cfg(feature = "gpu")]
+    const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        2 => Float32x4,
+        3 => Float32x4,
+        4 => Float32x4,
+        5 => Float32x4,
+        6 => Float32x4,
+    ];
+
+    #[cfg(feature = "gpu")]
+    pub(crate) fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+
+    // Clip-space depth of the square's center, larger is further away
+    fn depth(&self) -> f32 {
+        let [x, y, _, translation] = self.model;
+        0.5 * x[2] + 0.5 * y[2] + translation[2]
+    }
+
+    // Furthest first, squares at the same depth keep the order they were drawn in
+    pub(crate) fn sort_back_to_front(instances: &mut [InstanceRaw]) {
+        instances.sort_by(|a, b| b.depth().total_cmp(&a.depth()));
+    }
+}
+
+/// Builds a triangle fan for a circle inscribed in the unit square, so it
+/// shares the square's 0..1 local space. The first vertex is the center.
+pub fn circle_geometry(segments: u32) -> (Vec<Vertex>, Vec<u16>) {
+    let segments = segments.clamp(3, u16::MAX as u32 - 1);
+
+    let mut vertices = Vec::with_capacity(segments as usize + 1);
+    vertices.push(Vertex {
+        position: [0.5, 0.5, 0.0],
+        color: [1.0, 1.0, 1.0, 1.0],
+    });
+    for i in 0..segments {
+        let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+        vertices.push(Vertex {
+            position: [0.5 + 0.5 * angle.cos(), 0.5 + 0.5 * angle.sin(), 0.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+        });
+    }
+
+    // Wound like the square so back-face culling keeps it after the y-flip
+    let mut indices = Vec::with_capacity(segments as usize * 3);
+    for i in 0..segments {
+        let current = (i + 1) as u16;
+        let next = ((i + 1) % segments + 1) as u16;
+        indices.extend_from_slice(&[0, next, current]);
+    }
+
+    (vertices, indices)
+}
+
+/// Fans a convex polygon out from its first point. Concave polygons
+/// come out wrong. Points may go either way around, the triangles are
+/// wound like the pre-baked square so they survive back-face culling.
+pub fn polygon_geometry(points: &[Vec2]) -> (Vec<Vertex>, Vec<u16>) {
+    let points = &points[..points.len().min(u16::MAX as usize)];
+    if points.len() < 3 {
+        return (Vec::new(), Vec::new());
+    }
+
+    let vertices = points
+        .iter()
+        .map(|point| Vertex {
+            position: [point.x, point.y, 0.0],
+            color: [1.0, 1.0, 1.0, 1.0],
+        })
+        .collect();
+
+    // Shoelace formula, positive when the points go counter-clockwise
+    let area: f32 = points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.perp_dot(*b))
+        .sum();
+    let mut indices = Vec::with_capacity((points.len() - 2) * 3);
+    for i in 1..points.len() as u16 - 1 {
+        if area > 0.0 {
+            indices.extend_from_slice(&[0, i + 1, i]);
+        } else {
+            indices.extend_from_slice(&[0, i, i + 1]);
+        }
+    }
+
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::geometry::Transform;
+
+    #[test]
+    fn circle_fan_has_a_vertex_per_segment_and_the_center() {
+        for segments in [12, 32, 64] {
+            let (vertices, indices) = circle_geometry(segments);
+            assert_eq!(vertices.len(), segments as usize + 1);
+            assert_eq!(indices.len(), segments as usize * 3);
+            assert_eq!(vertices[0].position, [0.5, 0.5, 0.0]);
+        }
+        // Fewer than 3 segments can't enclose anything
+        assert_eq!(circle_geometry(1).0.len(), 4);
+    }
+
+    #[test]
+    fn pentagon_fans_into_three_triangles() {
+        let pentagon: Vec<Vec2> = (0..5)
+            .map(|i| Vec2::from_angle(i as f32 / 5.0 * std::f32::consts::TAU))
+            .collect();
+        let (vertices, indices) = polygon_geometry(&pentagon);
+
+        assert_eq!(vertices.len(), 5);
+        assert_eq!(indices, vec![0, 2, 1, 0, 3, 2, 0, 4, 3]);
+        // Going the other way round gives the same winding on screen
+        let reversed: Vec<Vec2> = pentagon.iter().rev().copied().collect();
+        assert_eq!(
+            polygon_geometry(&reversed).1,
+            vec![0, 1, 2, 0, 2, 3, 0, 3, 4]
+        );
+    }
+
+    #[test]
+    fn fewer_than_three_points_give_no_geometry() {
+        for count in 0..3 {
+            let (vertices, indices) = polygon_geometry(&vec![Vec2::ONE; count]);
+            assert!(vertices.is_empty() && indices.is_empty());
+        }
+    }
+
+    #[test]
+    fn higher_z_squares_sort_last() {
+        let instance = |z: f32| InstanceRaw {
+            model: Transform::ortographic_size_invariant()
+                .translate(Vec3::new(0.25, 0.25, z))
+                .as_raw(),
+            color: EngineColor::WHITE,
+        };
+        let mut instances = [instance(2.0), instance(-1.0), instance(0.5)];
+
+        InstanceRaw::sort_back_to_front(&mut instances);
+
+        assert_eq!(instances[0].model, instance(-1.0).model);
+        assert_eq!(instances[1].model, instance(0.5).model);
+        assert_eq!(instances[2].model, instance(2.0).model);
+    }
+}
@@ -0,0 +1,123 @@
+use glam::{Vec2, Vec3};
+
+use crate::{
+    color::EngineColor,
+    geometry::Transform,
+    renderer::{BlendMode, Drawer},
+    rng::Rng,
+};
+
+/// How a burst of particles looks and moves.
+#[derive(Debug, Clone, Copy)]
+pub struct EmitConfig {
+    /// Particles leave the origin at a random speed up to this, in normalized
+    /// units per second.
+    pub max_speed: f32,
+    /// Seconds until a particle is gone. It fades out over this time.
+    pub lifetime: f32,
+    /// Side of each square, in normalized units.
+    pub size: f32,
+    pub color: EngineColor,
+}
+
+impl Default for EmitConfig {
+    fn default() -> Self {
+        Self {
+            max_speed: 0.4,
+            lifetime: 0.4,
+            size: 0.01,
+            color: EngineColor::WHITE,
+        }
+    }
+}
+
+struct Particle {
+    position: Vec2,
+    velocity: Vec2,
+    age: f32,
+    lifetime: f32,
+    size: f32,
+    color: EngineColor,
+}
+
+/// A pool of short-lived squares, drawn through the batched square drawer.
+pub struct ParticleSystem {
+    particles: Vec<Particle>,
+    max_particles: usize,
+    // Scatters particles, it doesn't need to be unpredictable
+    rng: Rng,
+}
+
+impl ParticleSystem {
+    pub const DEFAULT_MAX_PARTICLES: usize = 512;
+
+    pub fn new(max_particles: usize) -> Self {
+        Self {
+            particles: Vec::with_capacity(max_particles),
+            max_particles,
+            rng: Rng::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Spawns up to `count` particles at `origin` flying in random directions.
+    /// Particles past the pool's capacity are dropped.
+    pub fn emit(&mut self, origin: Vec2, count: usize, config: &EmitConfig) {
+        let count = count.min(self.max_particles - self.particles.len());
+        for _ in 0..count {
+            let direction = Vec2::from_angle(self.rng.range(0.0, std::f32::consts::TAU));
+            let speed = self.rng.range(0.0, config.max_speed);
+            self.particles.push(Particle {
+                position: origin,
+                velocity: direction * speed,
+                age: 0.0,
+                lifetime: config.lifetime.max(f32::EPSILON),
+                size: config.size,
+                color: config.color,
+            });
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        for particle in &mut self.particles {
+            particle.position += particle.velocity * delta_time;
+            particle.age += delta_time;
+        }
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+    }
+
+    /// Draws every particle centered on its position, in the space of `t`.
+    /// Particles blend additively so overlapping ones glow.
+    pub fn render(&self, drawer: &mut Drawer, t: &Transform) {
+        let blend_mode = drawer.blend_mode();
+        drawer.set_blend_mode(BlendMode::Additive);
+        for particle in &self.particles {
+            let half = particle.size * 0.5;
+            let space = t
+                .translate(Vec3::new(
+                    particle.position.x - half,
+                    particle.position.y - half,
+                    0.0,
+                ))
+                .scale(Vec3::splat(particle.size));
+            let mut color = particle.color;
+            color.a *= 1.0 - particle.age / particle.lifetime; // Fade out over the lifetime
+            drawer.draw_square_batched(Some(&space), Some(&color));
+        }
+        drawer.set_blend_mode(blend_mode);
+    }
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_PARTICLES)
+    }
+}
@@ -0,0 +1,1091 @@
+use std::collections::HashMap;
+
+use glam::{Vec2, Vec3};
+use log::info;
+use winit::keyboard::KeyCode;
+
+use crate::{
+    collision::Collision, color::EngineColor, geometry::Transform, input::InputSystem, rng::Rng,
+    tween::Lerp,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Player {
+    A,
+    B,
+}
+
+/// Sizes and speeds of the paddles and ball, in normalized units, see
+/// `Game::init`. The defaults are the classic settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameConfig {
+    pub paddle_width: f32,
+    pub paddle_height: f32,
+    /// Top speed of a paddle, per second.
+    pub paddle_speed: f32,
+    /// How quickly a paddle picks up speed while a direction is held.
+    pub paddle_accel: f32,
+    /// How quickly it comes to rest once released.
+    pub paddle_friction: f32,
+    /// The side of the ball's square; it's drawn as the inscribed circle.
+    pub ball_size: f32,
+    /// Per second. Bounces keep the ball at this speed, see `BounceResponse`.
+    pub ball_speed: f32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            paddle_width: 0.2,
+            paddle_height: 0.05,
+            paddle_speed: 0.5,
+            paddle_accel: 4.0,
+            paddle_friction: 3.0,
+            ball_size: 0.05,
+            ball_speed: 0.5,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct PaddleState {
+    position: f32,
+    // Where the last update started, so balls can replay the paddle's move
+    previous_position: f32,
+    // Smoothed by paddle_accel and paddle_friction, passed on to the ball on hits
+    last_velocity: f32,
+    // Direction held this step, set by move_left and move_right
+    steer: f32,
+    // Seconds left of the hit flash, see flash
+    flash_timer: f32,
+}
+
+impl Default for PaddleState {
+    fn default() -> Self {
+        Self {
+            position: 0.5,
+            previous_position: 0.5,
+            last_velocity: 0.0,
+            steer: 0.0,
+            flash_timer: 0.0,
+        }
+    }
+}
+
+impl PaddleState {
+    const FLASH_DURATION: f32 = 0.15;
+    const FLASH_COLOR: EngineColor = EngineColor::WHITE;
+
+    pub fn local_space(
+        &self,
+        ortho_si: &Transform,
+        is_player_a: bool,
+        config: &GameConfig,
+    ) -> Transform {
+        // Position the origin at the top left
+
+        let horizontal_range = 1.0 - config.paddle_width;
+        let vertical_range = 1.0 - config.paddle_height;
+
+        let vertical_position = if is_player_a { 0.0 } else { 1.0 };
+
+        let x = self.position * horizontal_range;
+        let y = vertical_position * vertical_range;
+
+        ortho_si.translate(Vec3::new(x, y, 0.0)).scale(Vec3 {
+            x: config.paddle_width,
+            y: config.paddle_height,
+            z: 1.0,
+        })
+    }
+
+    pub fn goal_local_space(
+        &self,
+        ortho_si: &Transform,
+        is_player_a: bool,
+        config: &GameConfig,
+    ) -> Transform {
+        let vertical_position = if is_player_a { 0.0 } else { 1.0 };
+
+        let vertical_range = 1.0 - config.paddle_height;
+
+        let y = vertical_position * vertical_range;
+
+        ortho_si.translate(Vec3::new(0.0, y, 0.0)).scale(Vec3 {
+            x: 1.0,
+            y: config.paddle_height,
+            z: 1.0,
+        })
+    }
+
+    // Steering only sets the direction, update does the moving
+    pub fn move_left(&mut self) {
+        self.steer -= 1.0;
+    }
+    pub fn move_right(&mut self) {
+        self.steer += 1.0;
+    }
+    pub fn reset_steering(&mut self) {
+        self.steer = 0.0;
+    }
+
+    // Steers from the state of both keys at once. Holding both cancels out
+    // and stops the paddle dead, instead of one key winning.
+    pub fn steer_from_keys(&mut self, left: bool, right: bool) {
+        self.steer = match (left, right) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            (true, true) => {
+                self.last_velocity = 0.0;
+                0.0
+            }
+            (false, false) => 0.0,
+        };
+    }
+
+    // Ramps the velocity towards the held direction, or down to rest, and
+    // moves. The paddle stops dead at the sides.
+    pub fn update(&mut self, delta_time: f32, config: &GameConfig) {
+        self.flash_timer = (self.flash_timer - delta_time).max(0.0);
+        self.previous_position = self.position;
+
+        let (target, rate) = if self.steer != 0.0 {
+            (
+                self.steer.signum() * config.paddle_speed,
+                config.paddle_accel,
+            )
+        } else {
+            (0.0, config.paddle_friction)
+        };
+        let max_change = rate * delta_time;
+        self.last_velocity += (target - self.last_velocity).clamp(-max_change, max_change);
+
+        self.position += self.last_velocity * delta_time;
+        if !(0.0..=1.0).contains(&self.position) {
+            self.position = self.position.clamp(0.0, 1.0);
+            self.last_velocity = 0.0;
+        }
+    }
+
+    // Lights the paddle up in FLASH_COLOR, fading back over FLASH_DURATION
+    pub fn flash(&mut self) {
+        self.flash_timer = PaddleState::FLASH_DURATION;
+    }
+
+    // `base` with the hit flash blended in
+    pub fn color(&self, base: EngineColor) -> EngineColor {
+        base.lerp(
+            PaddleState::FLASH_COLOR,
+            self.flash_timer / PaddleState::FLASH_DURATION,
+        )
+    }
+
+    // Horizontal center of the paddle in normalized units
+    pub fn center_x(&self, config: &GameConfig) -> f32 {
+        self.position * (1.0 - config.paddle_width) + config.paddle_width / 2.0
+    }
+}
+
+/// How the ball comes off a paddle, see `Game::set_bounce_response`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BounceResponse {
+    /// Share of the speed into the paddle that the ball keeps coming out, 1
+    /// is a perfectly elastic bounce.
+    pub restitution: f32,
+    /// How much of the difference between the paddle's and the ball's motion
+    /// along the paddle face carries over on a hit, from 0 to 1. This is what
+    /// lets a moving paddle put spin on the ball.
+    pub friction: f32,
+    /// Keeps the ball at its fixed speed whatever the bounces do to it. The
+    /// ball can slow to a crawl without it if `restitution` is below 1.
+    pub constant_speed: bool,
+}
+
+impl Default for BounceResponse {
+    fn default() -> Self {
+        Self {
+            restitution: 1.0,
+            friction: 0.5,
+            constant_speed: true,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct DualPaddleState {
+    pub(crate) player_a: PaddleState,
+    pub(crate) player_b: PaddleState,
+    pub(crate) bounce: BounceResponse,
+}
+
+impl DualPaddleState {
+    pub fn local_spaces(
+        &self,
+        ortho_si: &Transform,
+        config: &GameConfig,
+    ) -> (Transform, Transform) {
+        (
+            self.player_a.local_space(ortho_si, true, config),
+            self.player_b.local_space(ortho_si, false, config),
+        )
+    }
+
+    // Player B's keys are ignored when it is driven by an AiController. These
+    // are physical keys, so the controls sit in the same place on every layout.
+    pub fn move_paddles(&mut self, input: &InputSystem, controls: &Controls, player_b_is_ai: bool) {
+        let is_down = |action| input.is_physical_key_down(controls.key(action));
+
+        self.player_a
+            .steer_from_keys(is_down(Action::PlayerALeft), is_down(Action::PlayerARight));
+        if player_b_is_ai {
+            // The AI steers later, from a clean slate
+            self.player_b.reset_steering();
+            return;
+        }
+        self.player_b
+            .steer_from_keys(is_down(Action::PlayerBLeft), is_down(Action::PlayerBRight));
+    }
+
+    // Forgets the paddles' last move, as if they had been standing still
+    pub fn hold_still(&mut self) {
+        self.player_a.previous_position = self.player_a.position;
+        self.player_b.previous_position = self.player_b.position;
+    }
+
+    pub fn flash(&mut self, player: Player) {
+        match player {
+            Player::A => self.player_a.flash(),
+            Player::B => self.player_b.flash(),
+        }
+    }
+
+    // Moves both paddles once everyone, human or AI, has steered
+    pub fn update(&mut self, delta_time: f32, config: &GameConfig) {
+        self.player_a.update(delta_time, config);
+        self.player_b.update(delta_time, config);
+    }
+}
+
+/// Something a player can bind a key to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[allow(clippy::enum_variant_names)] // Only paddle moves are bindable so far
+pub enum Action {
+    PlayerALeft,
+    PlayerARight,
+    PlayerBLeft,
+    PlayerBRight,
+}
+
+impl Action {
+    pub const ALL: [Action; 4] = [
+        Action::PlayerALeft,
+        Action::PlayerARight,
+        Action::PlayerBLeft,
+        Action::PlayerBRight,
+    ];
+
+    // Shown while waiting for a new key, fits on one line of the 8x8 font
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Action::PlayerALeft => "PLAYER A LEFT",
+            Action::PlayerARight => "PLAYER A RIGHT",
+            Action::PlayerBLeft => "PLAYER B LEFT",
+            Action::PlayerBRight => "PLAYER B RIGHT",
+        }
+    }
+}
+
+/// Which physical key drives each `Action`.
+///
+/// To rebind, call `listen_for` with the action, and the next key pressed
+/// becomes its binding. Escape cancels without changing anything.
+pub struct Controls {
+    bindings: HashMap<Action, KeyCode>,
+    // The action waiting for a key, see listen_for
+    listening: Option<Action>,
+}
+
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            bindings: HashMap::from([
+                (Action::PlayerALeft, KeyCode::KeyA),
+                (Action::PlayerARight, KeyCode::KeyD),
+                (Action::PlayerBLeft, KeyCode::ArrowLeft),
+                (Action::PlayerBRight, KeyCode::ArrowRight),
+            ]),
+            listening: None,
+        }
+    }
+}
+
+impl Controls {
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.bindings[&action]
+    }
+
+    /// Binds `key` to `action`. An action that already had `key` takes over
+    /// the old key of `action`, so no key ever drives two actions.
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        let old_key = self.key(action);
+        for bound in self.bindings.values_mut() {
+            if *bound == key {
+                *bound = old_key;
+            }
+        }
+        self.bindings.insert(action, key);
+    }
+
+    /// Waits for the next key press and binds it to `action`, see `update`.
+    pub fn listen_for(&mut self, action: Action) {
+        self.listening = Some(action);
+    }
+
+    pub fn cancel_listening(&mut self) {
+        self.listening = None;
+    }
+
+    /// The action waiting for a key, if a rebind is in progress.
+    pub fn listening(&self) -> Option<Action> {
+        self.listening
+    }
+
+    // Finishes a rebind once a key is pressed. Returns true while a rebind
+    // was in progress this frame, so the caller can ignore the key otherwise.
+    pub(crate) fn update(&mut self, input: &InputSystem) -> bool {
+        let Some(action) = self.listening else {
+            return false;
+        };
+        if let Some(key) = input.physical_keys_just_pressed().next() {
+            if key != KeyCode::Escape {
+                self.bind(action, key);
+            }
+            self.listening = None;
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    // Seconds between the AI re-reading the ball position
+    fn reaction_time(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Normal => 0.15,
+            Difficulty::Hard => 0.05,
+        }
+    }
+
+    // Largest offset from the ball the AI aims at, in normalized units
+    fn max_error(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.12,
+            Difficulty::Normal => 0.06,
+            Difficulty::Hard => 0.02,
+        }
+    }
+}
+
+/// Drives a paddle towards the ball through the same `move_left`/`move_right`
+/// calls a human would trigger, so paddle velocity still transfers to the ball.
+pub(crate) struct AiController {
+    difficulty: Difficulty,
+    reaction_time: f32,
+    max_error: f32,
+    // Time until the next look at the ball
+    reaction_timer: f32,
+    // Runs forever, drives the tracking error wobble
+    elapsed: f32,
+    target_x: f32,
+}
+
+impl AiController {
+    // Stops the paddle jittering around the target
+    const DEAD_ZONE: f32 = 0.01;
+
+    pub fn new(difficulty: Difficulty) -> Self {
+        Self {
+            difficulty,
+            reaction_time: difficulty.reaction_time(),
+            max_error: difficulty.max_error(),
+            reaction_timer: 0.0,
+            elapsed: 0.0,
+            target_x: 0.5,
+        }
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    pub fn update(
+        &mut self,
+        paddle: &mut PaddleState,
+        ball: &Ball,
+        delta_time: f32,
+        config: &GameConfig,
+    ) {
+        self.elapsed += delta_time;
+        self.reaction_timer -= delta_time;
+        if self.reaction_timer <= 0.0 {
+            // Deterministic wobble standing in for imprecise tracking
+            let error = self.max_error * (self.elapsed * 2.7).sin();
+            self.target_x = ball.center(config).x + error;
+            self.reaction_timer = self.reaction_time;
+        }
+
+        let offset = self.target_x - paddle.center_x(config);
+        if offset < -Self::DEAD_ZONE {
+            paddle.move_left();
+        } else if offset > Self::DEAD_ZONE {
+            paddle.move_right();
+        }
+    }
+}
+
+const MIN_IMPACT_PLAYBACK_RATE: f32 = 0.8;
+const MAX_IMPACT_PLAYBACK_RATE: f32 = 1.4;
+
+/// How fast to play a hit sound, so harder hits sound higher. `impact` is
+/// the speed into the surface as a share of the ball's speed: 0 for a
+/// graze plays at 0.8, a head-on hit at 1 plays at 1.4, and the rate rises
+/// linearly in between. Out of range impacts are clamped.
+fn impact_to_playback_rate(impact: f32) -> f32 {
+    let impact = if impact.is_nan() { 0.0 } else { impact };
+    MIN_IMPACT_PLAYBACK_RATE
+        + (MAX_IMPACT_PLAYBACK_RATE - MIN_IMPACT_PLAYBACK_RATE) * impact.clamp(0.0, 1.0)
+}
+
+// What a physics step of the ball hit, see Ball::step. Hits carry the
+// playback speed of their sound.
+#[derive(Default)]
+pub(crate) struct StepEvents {
+    pub(crate) wall_hit: Option<f32>,
+    pub(crate) paddle_hit: Option<(Player, f32)>,
+    pub(crate) scorer: Option<Player>,
+    // Indices into the obstacles the step was given, one per bounce
+    pub(crate) obstacle_hits: Vec<usize>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Ball {
+    pub(crate) position: Vec2,
+    pub(crate) velocity: Vec2,
+    // Radians per second the velocity turns, put on by moving paddles
+    spin: f32,
+    // Where the ball hit a wall or paddle during the last update, for effects
+    pub(crate) bounced_at: Option<Vec2>,
+}
+
+impl Ball {
+    // Largest angle a serve leaves at, measured from straight up or down
+    const SERVE_CONE: f32 = std::f32::consts::FRAC_PI_4;
+    // Spin per unit of paddle surface speed on a hit, and how quickly it wears off
+    const SPIN_PER_SURFACE_SPEED: f32 = 1.5;
+    const SPIN_DECAY: f32 = 1.5;
+
+    pub(crate) fn new(position: Vec2, velocity: Vec2) -> Self {
+        Self {
+            position,
+            velocity,
+            spin: 0.0,
+            bounced_at: None,
+        }
+    }
+
+    /// A ball in the middle of the court, heading towards a random player at
+    /// a random angle within the serve cone.
+    pub(crate) fn serve(rng: &mut Rng, config: &GameConfig) -> Self {
+        let direction = if rng.next_bool() { Vec2::Y } else { -Vec2::Y };
+        let angle = rng.range(-Self::SERVE_CONE, Self::SERVE_CONE);
+        Self::new(
+            Vec2::new(0.5, 0.5),
+            Vec2::from_angle(angle).rotate(direction) * config.ball_speed,
+        )
+    }
+
+    /// Where the ball will be over the next `steps` steps of `dt` seconds, as
+    /// top-left corners like `position`, with the paddles held still. Stops
+    /// early if the ball reaches a goal. Nothing is played or changed, so
+    /// obstacles the ball would destroy still deflect it later on.
+    pub fn predict(
+        &self,
+        steps: usize,
+        dt: f32,
+        paddles: &DualPaddleState,
+        obstacles: &[Transform],
+        ortho_si: &Transform,
+        config: &GameConfig,
+    ) -> Vec<Vec2> {
+        let mut ball = self.clone();
+        // Otherwise every step would replay the paddles' last move
+        let mut paddles = paddles.clone();
+        paddles.hold_still();
+        let mut positions = Vec::with_capacity(steps);
+        for _ in 0..steps {
+            let events = ball.step(dt, &paddles, obstacles, ortho_si, config);
+            if events.scorer.is_some() {
+                break;
+            }
+            positions.push(ball.position);
+        }
+        positions
+    }
+
+    // Moves the ball and resolves its hits, without side effects outside the ball
+    pub(crate) fn step(
+        &mut self,
+        delta_time: f32,
+        paddles: &DualPaddleState,
+        obstacles: &[Transform],
+        ortho_si: &Transform,
+        config: &GameConfig,
+    ) -> StepEvents {
+        self.bounced_at = None;
+        let mut events = StepEvents::default();
+
+        // Like the Magnus effect, spin pushes the ball sideways to its motion.
+        // Turning the velocity does that without changing the speed.
+        self.velocity = Vec2::from_angle(self.spin * delta_time).rotate(self.velocity);
+        self.spin *= (-Self::SPIN_DECAY * delta_time).exp();
+        let speed = if paddles.bounce.constant_speed {
+            config.ball_speed
+        } else {
+            self.velocity.length()
+        };
+        self.velocity = self.velocity.normalize_or_zero() * speed; // Normalize speed
+
+        // Sweep the ball in game space so a fast step can't skip a thin paddle
+        let identity = Transform::new();
+        let from = self.position;
+        let to = self.position + self.velocity * delta_time;
+        let ball_space = self.local_space(&identity, config);
+        let toi_a = Collision::sweep_space(
+            &ball_space,
+            from,
+            to,
+            &paddles.player_a.local_space(&identity, true, config),
+        );
+        let toi_b = Collision::sweep_space(
+            &ball_space,
+            from,
+            to,
+            &paddles.player_b.local_space(&identity, false, config),
+        );
+        let toi = match (toi_a, toi_b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.position = match toi {
+            Some(t) => from + (to - from) * t, // Stop at the contact point
+            None => to,
+        };
+        let mut hit_a = toi.is_some() && toi == toi_a;
+        let mut hit_b = toi.is_some() && toi == toi_b;
+        if toi.is_none() {
+            // The sweep only knows where the paddles ended up, not where they came from
+            if let Some(position) = self.sub_step_hit(from, to, &paddles.player_a, true, config) {
+                self.position = position;
+                hit_a = true;
+            } else if let Some(position) =
+                self.sub_step_hit(from, to, &paddles.player_b, false, config)
+            {
+                self.position = position;
+                hit_b = true;
+            }
+        }
+
+        if self.position.x < 0.0 {
+            self.position.x = 0.0;
+            self.velocity.x = -self.velocity.x; // Bounce off left wall
+            self.spin = -self.spin; // The bounce mirrors the curve along with the path
+            events.wall_hit = Some(self.wall_playback_rate(config));
+            self.bounced_at = Some(self.center(config));
+        } else if self.position.x > (1.0 - config.ball_size) {
+            self.position.x = 1.0 - config.ball_size;
+            self.velocity.x = -self.velocity.x; // Bounce off right wall
+            self.spin = -self.spin;
+            events.wall_hit = Some(self.wall_playback_rate(config));
+            self.bounced_at = Some(self.center(config));
+        }
+        if self.position.y < 0.0 {
+            self.position.y = 0.0;
+            self.velocity.y = -self.velocity.y; // Bounce off top wall
+        } else if self.position.y > (1.0 - config.ball_size) {
+            self.position.y = 1.0 - config.ball_size;
+            self.velocity.y = -self.velocity.y; // Bounce off bottom wall
+        }
+        // Obstacles are in game space too, and reflect the ball about their
+        // real surface normal, so rotated ones deflect it at an angle
+        let radius = config.ball_size / 2.0;
+        for (index, obstacle) in obstacles.iter().enumerate() {
+            let center = self.center(config);
+            let Some((normal, depth)) = Collision::circle_vs_space(center, radius, obstacle) else {
+                continue;
+            };
+            let normal = normal.truncate();
+            self.position += normal * depth;
+            let approach = self.velocity.dot(normal);
+            if approach < 0.0 {
+                self.velocity -= 2.0 * approach * normal;
+                self.spin = -self.spin;
+                events.wall_hit = Some(impact_to_playback_rate(-approach / config.ball_speed));
+                events.obstacle_hits.push(index);
+                self.bounced_at = Some(center - normal * radius);
+            }
+        }
+        // Paddle contacts treat the ball as a circle, in game space so normals are usable
+        let center = self.center(config);
+        let paddle_a_space = paddles.player_a.local_space(&identity, true, config);
+        let paddle_b_space = paddles.player_b.local_space(&identity, false, config);
+        let contact_a = Collision::circle_vs_space(center, radius, &paddle_a_space);
+        let contact_b = Collision::circle_vs_space(center, radius, &paddle_b_space);
+        if hit_a || contact_a.is_some() {
+            // Bounce off player A paddle
+            let point = self.contact_point(&paddle_a_space, config);
+            let speed = self.bounce_off_paddle(
+                contact_a,
+                Vec2::Y,
+                &paddles.player_a,
+                &paddles.bounce,
+                config,
+            );
+            self.bounced_at = Some(point);
+            events.paddle_hit = Some((Player::A, speed));
+        } else if hit_b || contact_b.is_some() {
+            // Bounce off player B paddle
+            let point = self.contact_point(&paddle_b_space, config);
+            let speed = self.bounce_off_paddle(
+                contact_b,
+                -Vec2::Y,
+                &paddles.player_b,
+                &paddles.bounce,
+                config,
+            );
+            self.bounced_at = Some(point);
+            events.paddle_hit = Some((Player::B, speed));
+        } else {
+            // Check if the ball is inside the goal area of either player
+            if Collision::do_spaces_collide(
+                &self.local_space(ortho_si, config),
+                &paddles.player_a.goal_local_space(ortho_si, true, config),
+            )
+            .is_some()
+            {
+                info!("Player B scores!");
+                events.scorer = Some(Player::B);
+            } else if Collision::do_spaces_collide(
+                &self.local_space(ortho_si, config),
+                &paddles.player_b.goal_local_space(ortho_si, false, config),
+            )
+            .is_some()
+            {
+                info!("Player A scores!");
+                events.scorer = Some(Player::A);
+            }
+        }
+        events
+    }
+
+    // A paddle moving further than the ball's radius in one step can pass
+    // right through it. When either moved that far, this replays the step in
+    // pieces no longer than the radius, moving both, and returns where the
+    // ball was at the first overlap.
+    fn sub_step_hit(
+        &self,
+        from: Vec2,
+        to: Vec2,
+        paddle: &PaddleState,
+        is_player_a: bool,
+        config: &GameConfig,
+    ) -> Option<Vec2> {
+        let radius = config.ball_size / 2.0;
+        let paddle_travel =
+            (paddle.position - paddle.previous_position).abs() * (1.0 - config.paddle_width);
+        let travel = from.distance(to).max(paddle_travel);
+        if travel <= radius {
+            return None;
+        }
+
+        let identity = Transform::new();
+        let steps = (travel / radius).ceil() as u32;
+        let mut moved = paddle.clone();
+        (1..=steps).find_map(|step| {
+            let t = step as f32 / steps as f32;
+            moved.position = paddle.previous_position.lerp(paddle.position, t);
+            let position = from.lerp(to, t);
+            let ball = Ball {
+                position,
+                ..self.clone()
+            };
+            Collision::do_spaces_collide(
+                &ball.local_space(&identity, config),
+                &moved.local_space(&identity, is_player_a, config),
+            )
+            .map(|_| position)
+        })
+    }
+
+    // Splits the velocity along the contact normal and the paddle face: the
+    // normal part bounces back scaled by restitution, the face part is pulled
+    // towards the paddle's own motion by friction. Also pushes the ball out of
+    // the paddle. `face_normal` is used when the sweep stopped the ball right
+    // at the paddle and there's no overlap to resolve. Returns the playback
+    // speed for the bounce sound.
+    fn bounce_off_paddle(
+        &mut self,
+        contact: Option<(Vec3, f32)>,
+        face_normal: Vec2,
+        paddle: &PaddleState,
+        response: &BounceResponse,
+        config: &GameConfig,
+    ) -> f32 {
+        let (normal, depth) = contact
+            .map(|(normal, depth)| (normal.truncate(), depth))
+            .unwrap_or((face_normal, 0.0));
+        self.position += normal * depth;
+
+        let approach = self.velocity.dot(normal);
+        if approach < 0.0 {
+            self.velocity -= (1.0 + response.restitution) * approach * normal;
+        }
+
+        // Scaled so the default half friction kicks the ball sideways by twice
+        // the paddle velocity, as the fixed kick used to
+        let surface_velocity = Vec2::new(paddle.last_velocity * 4.0, 0.0);
+        let tangent = normal.perp();
+        let slip = (surface_velocity - self.velocity).dot(tangent);
+        self.velocity += tangent * slip * response.friction.clamp(0.0, 1.0);
+        // Set so the ball curves the way the paddle was moving
+        self.spin = Self::SPIN_PER_SURFACE_SPEED * normal.perp_dot(surface_velocity);
+
+        if response.constant_speed {
+            // A dead stop has no direction left, send the ball off the face
+            self.velocity = self.velocity.normalize_or(normal) * config.ball_speed;
+        }
+        impact_to_playback_rate(-approach / config.ball_speed)
+    }
+
+    // Hit sound speed for a bounce off a side wall
+    fn wall_playback_rate(&self, config: &GameConfig) -> f32 {
+        impact_to_playback_rate(self.velocity.x.abs() / config.ball_speed)
+    }
+
+    // Where the ball's quad and `paddle_space` (both in game space) meet, the
+    // ball's center if their edges don't cross
+    fn contact_point(&self, paddle_space: &Transform, config: &GameConfig) -> Vec2 {
+        Collision::do_spaces_collide(&self.local_space(&Transform::new(), config), paddle_space)
+            .and_then(|collision| collision.contact_centroid())
+            .map_or(self.center(config), |point| point.truncate())
+    }
+
+    // Stereo position of the ball's sounds: where it last hit something, or
+    // where it is, so they follow it across the court
+    pub(crate) fn pan(&self, config: &GameConfig) -> f32 {
+        self.bounced_at.unwrap_or(self.center(config)).x * 2.0 - 1.0
+    }
+
+    // The ball is drawn as the circle inscribed in its quad
+    pub(crate) fn center(&self, config: &GameConfig) -> Vec2 {
+        self.position + Vec2::splat(config.ball_size / 2.0)
+    }
+
+    pub fn local_space(&self, ortho_si: &Transform, config: &GameConfig) -> Transform {
+        let x = self.position.x;
+        let y = self.position.y;
+
+        ortho_si
+            .translate(Vec3::new(x, y, 0.0))
+            .scale(Vec3::splat(config.ball_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::ElementState;
+
+    use super::*;
+    use crate::input::{InputEvent, InputRecording, Replay};
+
+    #[test]
+    fn opposing_keys_hold_the_paddle_still() {
+        let config = GameConfig::default();
+        let mut paddle = PaddleState {
+            last_velocity: 0.3,
+            ..Default::default()
+        };
+        let start = paddle.position;
+
+        paddle.steer_from_keys(true, true);
+        paddle.update(0.1, &config);
+
+        assert_eq!(paddle.position, start);
+        assert_eq!(paddle.last_velocity, 0.0);
+    }
+
+    #[test]
+    fn fast_paddle_hits_slow_ball() {
+        let config = GameConfig::default();
+        // The paddle crosses the whole court in one step, and the ball sits in
+        // its row, short of where it started and ended
+        let mut paddles = DualPaddleState::default();
+        paddles.player_a.previous_position = 0.0;
+        paddles.player_a.position = 1.0;
+        let mut ball = Ball::new(Vec2::new(0.475, 0.04), Vec2::new(0.0, -config.ball_speed));
+
+        let events = ball.step(
+            1.0 / 60.0,
+            &paddles,
+            &[],
+            &Transform::ortographic_size_invariant(),
+            &config,
+        );
+
+        assert!(matches!(events.paddle_hit, Some((Player::A, _))));
+        assert!(events.scorer.is_none());
+        assert!(ball.velocity.y > 0.0);
+    }
+
+    #[test]
+    fn spin_curves_the_ball() {
+        let config = GameConfig::default();
+        let paddles = DualPaddleState::default();
+        let ortho_si = Transform::ortographic_size_invariant();
+        let mut straight = Ball::new(Vec2::new(0.475, 0.3), Vec2::new(0.0, config.ball_speed));
+        let mut curved = Ball {
+            spin: 2.0,
+            ..straight.clone()
+        };
+
+        for _ in 0..20 {
+            straight.step(1.0 / 60.0, &paddles, &[], &ortho_si, &config);
+            curved.step(1.0 / 60.0, &paddles, &[], &ortho_si, &config);
+        }
+
+        assert_eq!(straight.position.x, 0.475);
+        assert!((curved.position.x - straight.position.x).abs() > 0.01);
+        assert!((curved.velocity.length() - config.ball_speed).abs() < 1e-4);
+        assert!(curved.spin < 2.0, "spin should wear off");
+    }
+
+    #[test]
+    fn rotated_obstacle_deflects_along_its_normal() {
+        let config = GameConfig::default();
+        let paddles = DualPaddleState::default();
+        // A square standing on its corner below the falling ball, which
+        // lands on its upper left face and should leave heading left
+        let obstacles = [Transform::from_trs(
+            Vec3::new(0.5, 0.5, 0.0),
+            std::f32::consts::FRAC_PI_4,
+            Vec3::new(0.1, 0.1, 1.0),
+        )];
+        let mut ball = Ball::new(Vec2::new(0.44, 0.35), Vec2::new(0.0, config.ball_speed));
+
+        let ortho_si = Transform::ortographic_size_invariant();
+        let mut hits = Vec::new();
+        for _ in 0..60 {
+            let events = ball.step(1.0 / 60.0, &paddles, &obstacles, &ortho_si, &config);
+            hits.extend(events.obstacle_hits);
+            if !hits.is_empty() {
+                break;
+            }
+        }
+
+        assert_eq!(hits, vec![0]);
+        assert!((ball.velocity.x + config.ball_speed).abs() < 1e-4);
+        assert!(ball.velocity.y.abs() < 1e-4);
+    }
+
+    #[test]
+    fn dead_bounce_at_constant_speed_leaves_the_paddle() {
+        let config = GameConfig::default();
+        let response = BounceResponse {
+            restitution: 0.0,
+            ..Default::default()
+        };
+        let mut ball = Ball::new(Vec2::new(0.5, 0.04), Vec2::new(0.0, -config.ball_speed));
+
+        ball.bounce_off_paddle(None, Vec2::Y, &PaddleState::default(), &response, &config);
+
+        assert!(ball.velocity.is_finite());
+        assert!(ball.velocity.y > 0.0);
+        assert!((ball.velocity.length() - config.ball_speed).abs() < 1e-5);
+    }
+
+    #[test]
+    fn frozen_step_leaves_the_ball_in_place() {
+        let config = GameConfig::default();
+        let mut paddles = DualPaddleState::default();
+        paddles.player_a.move_right();
+        let ortho_si = Transform::ortographic_size_invariant();
+        let mut ball = Ball {
+            spin: 2.0,
+            ..Ball::new(Vec2::new(0.475, 0.3), Vec2::new(0.0, config.ball_speed))
+        };
+        let before = ball.clone();
+
+        paddles.update(0.0, &config);
+        let events = ball.step(0.0, &paddles, &[], &ortho_si, &config);
+
+        assert!(events.scorer.is_none() && events.paddle_hit.is_none());
+        assert_eq!(ball.position, before.position);
+        assert_eq!(ball.velocity, before.velocity);
+        assert_eq!(ball.spin, before.spin);
+    }
+
+    #[test]
+    fn impact_playback_rate_stays_in_range() {
+        assert_eq!(impact_to_playback_rate(0.0), 0.8);
+        assert_eq!(impact_to_playback_rate(1.0), 1.4);
+        let halfway = impact_to_playback_rate(0.5);
+        assert!(halfway > 0.8 && halfway < 1.4);
+        // Separating contacts, and impacts harder than the ball's speed
+        assert_eq!(impact_to_playback_rate(-0.3), 0.8);
+        assert_eq!(impact_to_playback_rate(3.0), 1.4);
+        assert_eq!(impact_to_playback_rate(f32::NAN), 0.8);
+    }
+
+    // The parts of a match the input and the seed decide, stepped the way
+    // Game::step_match does it
+    struct TestMatch {
+        rng: Rng,
+        paddles: DualPaddleState,
+        ball: Ball,
+        ai: AiController,
+        score: (u32, u32),
+    }
+
+    impl TestMatch {
+        const STEP: f32 = 1.0 / 120.0;
+
+        fn new(seed: u64, config: &GameConfig) -> Self {
+            let mut rng = Rng::new(seed);
+            let ball = Ball::serve(&mut rng, config);
+            Self {
+                rng,
+                paddles: DualPaddleState::default(),
+                ball,
+                ai: AiController::new(Difficulty::Normal),
+                score: (0, 0),
+            }
+        }
+
+        fn step(&mut self, input: &InputSystem, controls: &Controls, config: &GameConfig) {
+            self.paddles.move_paddles(input, controls, true);
+            self.ai
+                .update(&mut self.paddles.player_b, &self.ball, Self::STEP, config);
+            self.paddles.update(Self::STEP, config);
+            let ortho_si = Transform::ortographic_size_invariant();
+            let events = self
+                .ball
+                .step(Self::STEP, &self.paddles, &[], &ortho_si, config);
+            match events.scorer {
+                Some(Player::A) => self.score.0 += 1,
+                Some(Player::B) => self.score.1 += 1,
+                None => return,
+            }
+            self.ball = Ball::serve(&mut self.rng, config);
+        }
+    }
+
+    #[test]
+    fn replayed_input_plays_the_match_out_the_same() {
+        let config = GameConfig::default();
+        let controls = Controls::default();
+        let mut live = InputSystem::default();
+        let mut recording = InputRecording::new(7);
+        let mut recorded = TestMatch::new(7, &config);
+
+        // Player A sweeps left and right for ten seconds
+        for step in 0..1200 {
+            let event = match step % 150 {
+                0 => Some((KeyCode::KeyA, ElementState::Pressed)),
+                60 => Some((KeyCode::KeyA, ElementState::Released)),
+                75 => Some((KeyCode::KeyD, ElementState::Pressed)),
+                135 => Some((KeyCode::KeyD, ElementState::Released)),
+                _ => None,
+            };
+            if let Some((key, state)) = event {
+                live.record(InputEvent::PhysicalKey(key, state));
+            }
+            recording.record(&live);
+            recorded.step(&live, &controls, &config);
+            live.end_frame();
+        }
+
+        let recording = InputRecording::from_json(&recording.to_json()).unwrap();
+        assert_eq!(recording.len(), 1200);
+        let mut replayed = TestMatch::new(recording.seed(), &config);
+        let mut replay = Replay::new(recording);
+        let idle = InputSystem::default();
+        while let Some(input) = replay.next_input(&idle) {
+            replayed.step(input, &controls, &config);
+        }
+
+        // The input moved the paddle, and a goal was scored, so a serve came
+        // from the seed partway through
+        assert_ne!(recorded.paddles.player_a.position, 0.5);
+        assert_ne!(recorded.score, (0, 0));
+        assert_eq!(
+            replayed.paddles.player_a.position,
+            recorded.paddles.player_a.position
+        );
+        assert_eq!(
+            replayed.paddles.player_b.position,
+            recorded.paddles.player_b.position
+        );
+        assert_eq!(replayed.ball.position, recorded.ball.position);
+        assert_eq!(replayed.ball.velocity, recorded.ball.velocity);
+        assert_eq!(replayed.score, recorded.score);
+    }
+
+    #[test]
+    fn next_key_pressed_gets_bound() {
+        let mut controls = Controls::default();
+        let mut input = InputSystem::default();
+        assert!(!controls.update(&input));
+
+        controls.listen_for(Action::PlayerALeft);
+        // Nothing pressed yet, keep listening but swallow the frame
+        assert!(controls.update(&input));
+        assert_eq!(controls.listening(), Some(Action::PlayerALeft));
+
+        input.record(InputEvent::PhysicalKey(
+            KeyCode::KeyJ,
+            ElementState::Pressed,
+        ));
+        assert!(controls.update(&input));
+        assert_eq!(controls.key(Action::PlayerALeft), KeyCode::KeyJ);
+        assert_eq!(controls.listening(), None);
+
+        // Escape gives up without touching the binding
+        input.end_frame();
+        controls.listen_for(Action::PlayerALeft);
+        input.record(InputEvent::PhysicalKey(
+            KeyCode::Escape,
+            ElementState::Pressed,
+        ));
+        assert!(controls.update(&input));
+        assert_eq!(controls.key(Action::PlayerALeft), KeyCode::KeyJ);
+        assert_eq!(controls.listening(), None);
+    }
+
+    #[test]
+    fn one_key_steers_towards_it() {
+        let config = GameConfig::default();
+        let mut paddle = PaddleState::default();
+        let start = paddle.position;
+
+        paddle.steer_from_keys(false, true);
+        paddle.update(0.1, &config);
+
+        assert!(paddle.position > start);
+    }
+}
@@ -1,136 +1,638 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use log::info;
 use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::Future,
     mem,
+    pin::Pin,
+    rc::Rc,
     sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
 };
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
 use wgpu::{
-    BindGroup, Buffer, Color, CommandBuffer, CommandEncoder, Device, Queue, RenderPass,
-    RenderPipeline, Surface, SurfaceConfiguration, TextureView,
+    BindGroup, Buffer, Color, CommandEncoder, Device, Queue, RenderPass, RenderPipeline, Surface,
+    SurfaceConfiguration, TextureView,
 };
-use winit::window::Window;
+use winit::{platform::web::WindowExtWebSys, window::Window};
 
-use crate::{game::Game, geometry::Transform};
+use crate::{
+    color::EngineColor,
+    game::Game,
+    geometry::{self, Camera2D, Transform},
+    mesh::{self, InstanceRaw, TexturedVertex, Vertex},
+    text::BitmapFont,
+};
+
+/// A GPU texture ready to be drawn with `Drawer::draw_textured_square`.
+/// Clones share the texture, so one from `RenderingSystem::load_texture_url`
+/// shows the real image everywhere once it has loaded.
+#[derive(Clone)]
+pub struct TextureHandle {
+    inner: Rc<RefCell<GpuTexture>>,
+}
+
+struct GpuTexture {
+    texture: wgpu::Texture,
+    bind_group: BindGroup,
+    // Still the placeholder, waiting for the image to arrive
+    loading: bool,
+}
+
+impl TextureHandle {
+    pub fn width(&self) -> u32 {
+        self.inner.borrow().texture.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.inner.borrow().texture.height()
+    }
+
+    /// Whether this still shows the placeholder of a texture being fetched.
+    /// Failed loads stop loading and keep the placeholder.
+    pub fn is_loading(&self) -> bool {
+        self.inner.borrow().loading
+    }
+
+    fn bind_group(&self) -> BindGroup {
+        self.inner.borrow().bind_group.clone()
+    }
+}
+
+enum TextureLoadState {
+    Loading,
+    Done(web_sys::ImageBitmap),
+    Failed,
+}
+
+// A texture fetched by load_texture_url, swapped into its handle by render
+struct PendingTexture {
+    state: Rc<RefCell<TextureLoadState>>,
+    handle: TextureHandle,
+}
+
+/// Where `RenderingSystem::render` draws the game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderTarget {
+    /// Straight to the swapchain, at whatever size the canvas has.
+    Surface,
+    /// Into a texture at the game's target size, which is then blitted to the
+    /// swapchain. Keeps pixel art sharp and gives post-processing a hook.
+    Offscreen,
+}
+
+/// Samples per pixel for the drawer's color and depth targets, see
+/// `RenderingSystem::set_msaa`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Samples {
+    One,
+    /// 4x multisampling, which WebGL2 always supports. Smooths the edges of
+    /// rotated and diagonal geometry.
+    Four,
+}
+
+impl Samples {
+    pub fn count(self) -> u32 {
+        match self {
+            Samples::One => 1,
+            Samples::Four => 4,
+        }
+    }
+}
+
+/// How the offscreen texture is stretched over the surface when it is blitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalingMode {
+    /// Covers the whole surface, which already has the target aspect ratio.
+    Fit,
+    /// Scales by the largest whole factor that fits, centered with black
+    /// borders and sampled nearest, so every game pixel is the same size.
+    /// Falls back to `Fit` while the surface is smaller than the target.
+    IntegerNearest,
+}
+
+/// A full-screen effect applied while blitting the offscreen texture. It only
+/// changes the final image, the game's coordinate space is unaffected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostEffect {
+    None,
+    /// Darkens the gaps between rows of game pixels.
+    Scanlines,
+    /// Scanlines on a curved screen.
+    Crt,
+}
+
+/// How `EngineColor` values are meant to be read. The renderer converts them
+/// for whichever surface format the browser gave it, so a color looks the
+/// same on sRGB and plain formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Gamma encoded, like hex codes from an image editor or CSS. The default,
+    /// and what the `EngineColor` constants are written in.
+    Srgb,
+    /// Linear light, for colors that come out of lighting or blending math.
+    Linear,
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Vertex {
-    pub position: [f32; 3],
-    pub color: [f32; 3],
-}
-
-impl Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
+// Mirrors PostParams in the shader
+struct PostParamsRaw {
+    effect: u32,
+    scanline_intensity: f32,
+    curvature: f32,
+    // Height of the offscreen texture, so scanlines line up with game pixels
+    rows: f32,
+    // 1 when the blit has to gamma encode itself, see blit_offscreen
+    encode_srgb: u32,
+    // Uniform blocks on WebGL are sized in 16 byte steps
+    _padding: [u32; 3],
+}
+
+/// How a draw's color combines with what's already in the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Overwrites the target, ignoring alpha.
+    Replace,
+    /// Regular transparency, the default.
+    Alpha,
+    /// Adds the color on top, weighted by alpha. Good for glows.
+    Additive,
+    /// Multiplies the target by the color, for shadows and tints.
+    Multiply,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 4] = [
+        BlendMode::Replace,
+        BlendMode::Alpha,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+    ];
+
+    fn state(self) -> wgpu::BlendState {
+        match self {
+            BlendMode::Replace => wgpu::BlendState::REPLACE,
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
                 },
-                wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x3,
+                alpha: wgpu::BlendComponent::OVER,
+            },
+            BlendMode::Multiply => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::Zero,
+                    operation: wgpu::BlendOperation::Add,
                 },
-            ],
+                alpha: wgpu::BlendComponent::OVER,
+            },
         }
     }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-/// Represents a color in RGBA format.
-pub struct EngineColor {
-    pub r: f32,
-    pub g: f32,
-    pub b: f32,
-    pub a: f32,
-}
-
-impl EngineColor {
-    pub const WHITE: Self = Self {
-        r: 1.0,
-        g: 1.0,
-        b: 1.0,
-        a: 1.0,
-    };
-    pub const BLACK: Self = Self {
-        r: 0.0,
-        g: 0.0,
-        b: 0.0,
-        a: 1.0,
-    };
-    pub const RED: Self = Self {
-        r: 1.0,
-        g: 0.0,
-        b: 0.0,
-        a: 1.0,
-    };
-    pub const GREEN: Self = Self {
-        r: 0.0,
-        g: 1.0,
-        b: 0.0,
-        a: 1.0,
-    };
-    pub const BLUE: Self = Self {
-        r: 0.0,
-        g: 0.0,
-        b: 1.0,
-        a: 1.0,
-    };
-    pub const PURPLE: Self = Self {
-        r: 0.5,
-        g: 0.0,
-        b: 0.5,
-        a: 1.0,
-    };
+// What a set of draw pipelines is built for, see RenderingSystem::pipelines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    blend_mode: BlendMode,
+    depth_test: bool,
+    // Off for mirrored draws, see Drawer::set_backface_culling
+    cull_backfaces: bool,
+    // Of the target drawn into, see RenderingSystem::draw_format
+    format: wgpu::TextureFormat,
+}
+
+// The pipelines the drawer picks from, built the first time a PipelineKey is
+// drawn with. Cloning only copies handles.
+#[derive(Clone)]
+struct DrawPipelines {
+    flat: RenderPipeline,
+    instanced: RenderPipeline,
+    textured: RenderPipeline,
+}
+
+// A circle fan kept on the GPU, see Drawer::draw_circle_segments
+struct CircleGeometry {
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    num_indices: u32,
+}
+
+// The texture the game is drawn into when rendering offscreen.
+//
+// Colors flow through it like this: `EngineColor`s are converted to linear
+// light by surface_color, and textures are sampled as linear. The offscreen
+// texture uses the sRGB variant of the surface format, so the GPU blends in
+// linear light, then gamma encodes on write. The blit samples it back as
+// linear and writes to the surface, which encodes again if it's sRGB, or
+// has the shader encode when it isn't. Without an sRGB variant (float
+// formats) the texture is linear and nothing is encoded at all.
+struct OffscreenTarget {
+    // Kept for copies out of it, see capture_frame
+    texture: wgpu::Texture,
+    view: TextureView,
+    // Drawn into instead of `view` and resolved to it when multisampling
+    msaa_view: Option<TextureView>,
+    depth_view: TextureView,
+    // Samples the texture with the blit sampler, for the final pass
+    bind_group: BindGroup,
+}
+
+// Maps the components one to one. The drawer converts colors for the surface
+// with RenderingSystem::surface_color before they get here
+impl From<EngineColor> for Color {
+    fn from(color: EngineColor) -> Self {
+        Self {
+            r: color.r as f64,
+            g: color.g as f64,
+            b: color.b as f64,
+            a: color.a as f64,
+        }
+    }
+}
+
+// Where a frame capture's result lands once the GPU has copied it out
+#[derive(Default)]
+struct CaptureSlot {
+    // Outer None while the readback is still in flight
+    result: Option<Option<Vec<u8>>>,
+    waker: Option<Waker>,
+}
+
+impl CaptureSlot {
+    fn finish(slot: &RefCell<Self>, png: Option<Vec<u8>>) {
+        let mut slot = slot.borrow_mut();
+        slot.result = Some(png);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A frame being captured, see `RenderingSystem::capture_frame`. Resolves to
+/// the PNG bytes, or `None` if the frame couldn't be read back.
+pub struct FrameCapture {
+    slot: Rc<RefCell<CaptureSlot>>,
+}
+
+impl Future for FrameCapture {
+    type Output = Option<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.borrow_mut();
+        match slot.result.take() {
+            Some(png) => Poll::Ready(png),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Counters for the work one call to `RenderingSystem::render` did.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub submits: u32,
+    /// Uploads through `queue.write_buffer`.
+    pub buffer_writes: u32,
+    /// Transient vertex, index and instance buffers the pool had to allocate
+    /// for the frame, see `BufferPool`.
+    pub buffers_created: u32,
+    /// Transient buffers the pool handed out again instead of allocating.
+    pub buffers_reused: u32,
+    /// CPU time spent in `render`, in milliseconds.
+    pub frame_time_ms: f64,
+}
+
+/// Recycles the transient buffers drawers make for dynamic geometry, like
+/// polygons, text and instance batches, so rebuilding them every frame
+/// doesn't allocate. Buffers are bucketed by usage and a power of two
+/// capacity, handed out for one frame and reclaimed once it's submitted.
+pub struct BufferPool {
+    free: Vec<PooledBuffer>,
+    in_use: Vec<PooledBuffer>,
+    hits: u32,
+    misses: u32,
+}
+
+struct PooledBuffer {
+    usage: wgpu::BufferUsages,
+    capacity: u64,
+    buffer: Buffer,
+}
+
+impl BufferPool {
+    // Small buffers are rounded up to this so they can serve each other
+    const MIN_CAPACITY: u64 = 256;
+
+    pub fn new() -> Self {
+        Self {
+            free: Vec::new(),
+            in_use: Vec::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// A buffer of at least `contents.len()` bytes holding `contents`, valid
+    /// until the next `reclaim`. Anything past `contents` is left over from
+    /// earlier use, so draws must only read what they wrote.
+    pub fn upload(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        usage: wgpu::BufferUsages,
+        contents: &[u8],
+    ) -> Buffer {
+        let align = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+        let size = contents.len().next_multiple_of(align) as u64;
+        let capacity = size.max(Self::MIN_CAPACITY).next_power_of_two();
+
+        let reusable = self
+            .free
+            .iter()
+            .position(|pooled| pooled.usage == usage && pooled.capacity == capacity);
+        let pooled = match reusable {
+            Some(index) => {
+                self.hits += 1;
+                self.free.swap_remove(index)
+            }
+            None => {
+                self.misses += 1;
+                PooledBuffer {
+                    usage,
+                    capacity,
+                    buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Pooled Buffer"),
+                        size: capacity,
+                        usage: usage | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                }
+            }
+        };
+
+        // Writes have to be a multiple of the copy alignment
+        if contents.len().is_multiple_of(align) {
+            queue.write_buffer(&pooled.buffer, 0, contents);
+        } else {
+            let mut padded = contents.to_vec();
+            padded.resize(size as usize, 0);
+            queue.write_buffer(&pooled.buffer, 0, &padded);
+        }
+
+        let buffer = pooled.buffer.clone();
+        self.in_use.push(pooled);
+        buffer
+    }
+
+    /// Makes every buffer handed out since the last call available again.
+    /// Only call this after the draws using them have been submitted.
+    pub fn reclaim(&mut self) {
+        self.free.append(&mut self.in_use);
+    }
+
+    /// Drops the idle buffers, e.g. after a scene that needed unusually
+    /// large ones.
+    pub fn trim(&mut self) {
+        self.free.clear();
+    }
+
+    /// How many uploads reused a buffer and how many had to allocate, since
+    /// the last call.
+    pub fn take_stats(&mut self) -> (u32, u32) {
+        (mem::take(&mut self.hits), mem::take(&mut self.misses))
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why `RenderingSystem::new` couldn't set up the GPU.
+#[derive(Debug)]
+pub enum RendererInitError {
+    CreateSurface(wgpu::CreateSurfaceError),
+    // Usually means the browser has no WebGL2
+    RequestAdapter(wgpu::RequestAdapterError),
+    RequestDevice(wgpu::RequestDeviceError),
+    // The surface reported no formats, present modes or alpha modes
+    UnsupportedSurface,
+}
+
+impl std::fmt::Display for RendererInitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RendererInitError::CreateSurface(err) => {
+                write!(f, "couldn't create a drawing surface: {}", err)
+            }
+            RendererInitError::RequestAdapter(err) => {
+                write!(f, "no compatible graphics adapter: {}", err)
+            }
+            RendererInitError::RequestDevice(err) => {
+                write!(f, "couldn't open the graphics device: {}", err)
+            }
+            RendererInitError::UnsupportedSurface => {
+                write!(f, "the drawing surface has no supported configuration")
+            }
+        }
+    }
 }
 
+impl std::error::Error for RendererInitError {}
+
 pub struct RenderingSystem {
     surface: Surface<'static>,
     device: Device,
     queue: Queue,
     config: SurfaceConfiguration,
+    // What the surface reported it can present with, see set_present_mode
+    present_modes: Vec<wgpu::PresentMode>,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: RenderPipeline,
+    // Behind a RefCell since they're built from draw_pipelines, which only
+    // borrows the renderer
+    pipelines: RefCell<HashMap<PipelineKey, DrawPipelines>>,
+    depth_test: bool,
+    // Kept to build pipelines the first time they're drawn with
+    shader: wgpu::ShaderModule,
+    draw_pipeline_layouts: [wgpu::PipelineLayout; 3],
+    msaa: Samples,
+    // Sized like the surface, recreated in resize
+    depth_view: TextureView,
+    // Multisampled color target resolved to the surface, see set_msaa
+    msaa_view: Option<TextureView>,
     target_aspect_ratio: f32,
     original_size: winit::dpi::PhysicalSize<u32>,
+    // Size of the canvas element as reported by the window, which is what mouse positions refer to
+    window_size: winit::dpi::PhysicalSize<u32>,
+    // Checked before every frame, see is_visible
+    canvas: Option<web_sys::HtmlCanvasElement>,
+    // Latest size from resize, not applied to the surface yet
+    pending_size: Option<winit::dpi::PhysicalSize<u32>>,
 
     // For transforms:
     transform_buffer: Buffer,
     transform_bind_group: BindGroup,
+    // Distance between two draws' slots in the transform and color buffers
+    uniform_stride: u64,
+    // Page of the uniform buffers the next submit writes, see UNIFORM_PAGES
+    uniform_page: Cell<u32>,
     ortographic_transform: Transform,
 
     // For pre-baked geometry:
     square_vertex_buffer: Buffer,
     square_index_buffer: Buffer,
+    circle_vertex_buffer: Buffer,
+    circle_index_buffer: Buffer,
+    circle_segments: u32,
+    // Fans for draw_circle_segments by segment count, see CIRCLE_CACHE_SIZE
+    circle_cache: RefCell<HashMap<u32, CircleGeometry>>,
 
     // For uniform color
     color_buffer: Buffer,
     color_bind_group: BindGroup,
+
+    // For textures:
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    texture_sampler: wgpu::Sampler,
+    textured_square_vertex_buffer: Buffer,
+
+    // For offscreen rendering:
+    render_target: RenderTarget,
+    offscreen: OffscreenTarget,
+    blit_pipeline: RenderPipeline,
+    blit_sampler: wgpu::Sampler,
+    scaling_mode: ScalingMode,
+    color_space: ColorSpace,
+
+    // For post-processing:
+    post_effect: PostEffect,
+    scanline_intensity: f32,
+    crt_curvature: f32,
+    post_params_buffer: Buffer,
+    post_params_bind_group: BindGroup,
+
+    // Behind a RefCell since drawers only borrow the renderer
+    buffer_pool: RefCell<BufferPool>,
+    // Captures waiting for the next frame, see capture_frame
+    capture_requests: Vec<Rc<RefCell<CaptureSlot>>>,
+    // Textures still being fetched, see load_texture_url
+    texture_loads: Vec<PendingTexture>,
+    stats: RenderStats,
+}
+
+// A draw recorded by the drawer. Nothing reaches the GPU until flush, which
+// uploads every uniform slot at once and encodes all commands into one submit.
+enum DrawCommand {
+    // Starts a new pass clearing the depth buffer, and the color too if given
+    Clear(Option<Color>),
+    Geometry {
+        layer: i32,
+        blend_mode: BlendMode,
+        cull_backfaces: bool,
+        textured: Option<BindGroup>,
+        vertex_buffer: Buffer,
+        index_buffer: Buffer,
+        num_indices: u32,
+        // Index into the drawer's transform and color slots
+        slot: u32,
+    },
+    Batch {
+        layer: i32,
+        blend_mode: BlendMode,
+        cull_backfaces: bool,
+        instance_buffer: Buffer,
+        count: u32,
+    },
+}
+
+impl DrawCommand {
+    fn layer(&self) -> i32 {
+        match self {
+            DrawCommand::Clear(_) => 0, // Never sorted, see sort_by_layer
+            DrawCommand::Geometry { layer, .. } | DrawCommand::Batch { layer, .. } => *layer,
+        }
+    }
+
+    // Stable, so draws on the same layer keep their order. Clears stay where
+    // they are and nothing moves across them.
+    fn sort_by_layer(commands: &mut [DrawCommand]) {
+        for run in commands.split_mut(|command| matches!(command, DrawCommand::Clear(_))) {
+            run.sort_by_key(DrawCommand::layer);
+        }
+    }
 }
 
 pub struct Drawer<'a> {
     //pass: RenderPass<'a>,
     pub renderer: &'a RenderingSystem,
     view: &'a TextureView,
-    command_buffers: Vec<CommandBuffer>,
+    // Where `view` is resolved to when it is multisampled
+    resolve_target: Option<&'a TextureView>,
+    depth_view: &'a TextureView,
+    commands: Vec<DrawCommand>,
+    // Per-draw uniforms, uploaded together on flush
+    transforms: Vec<[[f32; 4]; 4]>,
+    colors: Vec<EngineColor>,
+    // What the next slow draw uses, see apply_transform and set_color
+    transform: Transform,
+    color: EngineColor,
+    blend_mode: BlendMode,
+    backface_culling: bool,
+    // Sorts alpha blended batches by depth, see set_transparency_sort
+    transparency_sort: bool,
+    // Draws on higher layers go over lower ones, see set_layer
+    layer: i32,
+    stats: RenderStats,
     pub ortho: &'a Transform,
+    // Squares queued by draw_square_batched, emitted as one instanced draw
+    batch: Vec<InstanceRaw>,
+    // Clip-space view applied on top of every draw, if a camera is set
+    camera: Option<Transform>,
 }
 
 impl RenderingSystem {
-    pub async fn new(window: Arc<Window>, width: u32, height: u32) -> Self {
+    pub const DEFAULT_CIRCLE_SEGMENTS: u32 = 32;
+    // Segment counts draw_circle_segments keeps buffers for, first come first
+    // served. A game only tends to use a handful.
+    const CIRCLE_CACHE_SIZE: usize = 8;
+    // Public so custom pipelines can match the depth attachment, see device
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
+    // Uniform slots per submit, the drawer submits early if a frame needs more
+    const MAX_DRAWS_PER_SUBMIT: u32 = 256;
+    // The uniform buffers hold this many submits' worth of slots, used in
+    // turn, so a write never lands on slots the previous submit still reads
+    const UNIFORM_PAGES: u32 = 3;
+    pub const DEFAULT_SCANLINE_INTENSITY: f32 = 0.35;
+    pub const DEFAULT_CRT_CURVATURE: f32 = 0.08;
+
+    pub async fn new(
+        window: Arc<Window>,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, RendererInitError> {
         let target_aspect_ratio = width as f32 / height as f32;
         let size = winit::dpi::PhysicalSize::new(width, height);
+        // Already in device pixels, see WebApp::resumed
+        let window_size = window.inner_size();
+        let canvas = window.canvas();
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
             backends: wgpu::Backends::GL,
             ..Default::default()
         });
 
-        let surface = instance.create_surface(window).unwrap();
+        let surface = instance
+            .create_surface(window)
+            .map_err(RendererInitError::CreateSurface)?;
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -139,7 +641,7 @@ impl RenderingSystem {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .map_err(RendererInitError::RequestAdapter)?;
 
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
@@ -150,23 +652,31 @@ impl RenderingSystem {
                 trace: wgpu::Trace::default(),
             })
             .await
-            .unwrap();
+            .map_err(RendererInitError::RequestDevice)?;
 
         let surface_caps = surface.get_capabilities(&adapter);
+        let (Some(&first_format), Some(&present_mode), Some(&alpha_mode)) = (
+            surface_caps.formats.first(),
+            surface_caps.present_modes.first(),
+            surface_caps.alpha_modes.first(),
+        ) else {
+            return Err(RendererInitError::UnsupportedSurface);
+        };
         let surface_format = surface_caps
             .formats
             .iter()
             .copied()
             .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+            .unwrap_or(first_format);
+        info!("Surface format: {:?}", surface_format);
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
             width: size.width.max(1),
             height: size.height.max(1),
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
+            present_mode,
+            alpha_mode,
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
@@ -179,9 +689,13 @@ impl RenderingSystem {
             source: wgpu::ShaderSource::Wgsl(shader_source.into()),
         });
 
+        // Each draw gets its own slot in the uniform buffers, picked with a dynamic offset
+        let uniform_stride = mem::size_of::<[[f32; 4]; 4]>()
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as usize)
+            as u64;
         let transform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Transform Buffer"),
-            size: 4 * 4 * mem::size_of::<f32>() as u64, // 4x4 matrix
+            size: uniform_stride * (Self::MAX_DRAWS_PER_SUBMIT * Self::UNIFORM_PAGES) as u64,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
@@ -194,7 +708,7 @@ impl RenderingSystem {
                     visibility: wgpu::ShaderStages::VERTEX,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
+                        has_dynamic_offset: true,
                         min_binding_size: None,
                     },
                     count: None,
@@ -203,7 +717,7 @@ impl RenderingSystem {
 
         let color_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Color Buffer"),
-            size: mem::size_of::<EngineColor>() as u64, // 4 bytes for RGBA
+            size: uniform_stride * (Self::MAX_DRAWS_PER_SUBMIT * Self::UNIFORM_PAGES) as u64,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
@@ -216,7 +730,7 @@ impl RenderingSystem {
                     visibility: wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
+                        has_dynamic_offset: true,
                         min_binding_size: None,
                     },
                     count: None,
@@ -230,18 +744,111 @@ impl RenderingSystem {
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Texture Bind Group Layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        // Nearest filtering keeps pixel art crisp at the low target resolution
+        let texture_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let textured_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Textured Pipeline Layout"),
+                bind_group_layouts: &[
+                    &transform_bind_group_layout,
+                    &color_bind_group_layout,
+                    &texture_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let post_params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Params Buffer"),
+            size: mem::size_of::<PostParamsRaw>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let post_params_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Post Params Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+
+        let post_params_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Params Bind Group"),
+            layout: &post_params_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &post_params_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            }],
+        });
+
+        let blit_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit Pipeline Layout"),
+            bind_group_layouts: &[
+                &transform_bind_group_layout,
+                &color_bind_group_layout,
+                &texture_bind_group_layout,
+                &post_params_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+
+        // Draws a fullscreen triangle, the vertices come from the vertex index
+        let blit_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Blit Pipeline"),
+            layout: Some(&blit_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader,
-                entry_point: Some("vs_main"),
-                buffers: &[Vertex::desc()],
+                entry_point: Some("vs_blit"),
+                buffers: &[],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
                 module: &shader,
-                entry_point: Some("fs_main"),
+                entry_point: Some("fs_blit"),
                 targets: &[Some(wgpu::ColorTargetState {
                     format: config.format,
                     blend: Some(wgpu::BlendState::REPLACE),
@@ -253,7 +860,7 @@ impl RenderingSystem {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
+                cull_mode: None,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
@@ -268,6 +875,31 @@ impl RenderingSystem {
             cache: None,
         });
 
+        let blit_sampler = Self::create_blit_sampler(&device, wgpu::FilterMode::Nearest);
+        let offscreen_format = config.format.add_srgb_suffix(); // See offscreen_format
+        let offscreen = Self::create_offscreen_target(
+            &device,
+            Samples::One,
+            &texture_bind_group_layout,
+            &blit_sampler,
+            offscreen_format,
+            size,
+        );
+
+        let instanced_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Instanced Pipeline Layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+
+        let draw_pipeline_layouts = [
+            render_pipeline_layout,
+            instanced_pipeline_layout,
+            textured_pipeline_layout,
+        ];
+        let depth_view = Self::create_depth_view(&device, size, Samples::One);
+
         let transform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Transform Bind Group"),
             layout: &transform_bind_group_layout,
@@ -276,7 +908,7 @@ impl RenderingSystem {
                 resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
                     buffer: &transform_buffer,
                     offset: 0,
-                    size: None,
+                    size: wgpu::BufferSize::new(mem::size_of::<[[f32; 4]; 4]>() as u64),
                 }),
             }],
         });
@@ -289,7 +921,7 @@ impl RenderingSystem {
                 resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
                     buffer: &color_buffer,
                     offset: 0,
-                    size: None,
+                    size: wgpu::BufferSize::new(mem::size_of::<EngineColor>() as u64),
                 }),
             }],
         });
@@ -309,19 +941,19 @@ impl RenderingSystem {
         let square_vertices = [
             Vertex {
                 position: [0.0, 0.0, 0.0],
-                color: [1.0, 1.0, 1.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             }, // Top Left
             Vertex {
                 position: [0.0, 1.0, 0.0],
-                color: [1.0, 1.0, 1.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             }, // Bottom Left
             Vertex {
                 position: [1.0, 1.0, 0.0],
-                color: [1.0, 1.0, 1.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             }, // Bottom Right
             Vertex {
                 position: [1.0, 0.0, 0.0],
-                color: [1.0, 1.0, 1.0],
+                color: [1.0, 1.0, 1.0, 1.0],
             }, // Top Right
         ];
 
@@ -330,89 +962,1082 @@ impl RenderingSystem {
         let square_vertex_buffer = Self::create_vertex_buffer_internal(&device, &square_vertices);
         let square_index_buffer = Self::create_index_buffer_internal(&device, square_indices);
 
-        Self {
+        let (circle_vertices, circle_indices) =
+            mesh::circle_geometry(Self::DEFAULT_CIRCLE_SEGMENTS);
+        let circle_vertex_buffer = Self::create_vertex_buffer_internal(&device, &circle_vertices);
+        let circle_index_buffer = Self::create_index_buffer_internal(&device, &circle_indices);
+
+        // Same corners as the flat square, with UVs matching the 0..1 local space
+        let textured_square_vertices = square_vertices.map(|v| TexturedVertex {
+            position: v.position,
+            color: [v.color[0], v.color[1], v.color[2]],
+            uv: [v.position[0], v.position[1]],
+        });
+        let textured_square_vertex_buffer =
+            Self::create_textured_vertex_buffer_internal(&device, &textured_square_vertices);
+
+        let mut renderer = Self {
             surface,
             device,
             queue,
             config,
+            present_modes: surface_caps.present_modes,
             size,
-            render_pipeline,
+            pipelines: RefCell::new(HashMap::new()),
+            depth_test: false,
+            shader,
+            draw_pipeline_layouts,
+            msaa: Samples::One,
+            depth_view,
+            msaa_view: None,
             transform_buffer,
             transform_bind_group,
+            uniform_stride,
+            uniform_page: Cell::new(0),
             ortographic_transform,
             square_vertex_buffer,
             square_index_buffer,
+            circle_vertex_buffer,
+            circle_index_buffer,
+            circle_segments: Self::DEFAULT_CIRCLE_SEGMENTS,
+            circle_cache: RefCell::new(HashMap::new()),
             color_buffer,
             color_bind_group,
             target_aspect_ratio,
             original_size: size,
-        }
+            window_size: size,
+            canvas,
+            pending_size: None,
+            texture_bind_group_layout,
+            texture_sampler,
+            textured_square_vertex_buffer,
+            render_target: RenderTarget::Surface,
+            offscreen,
+            blit_pipeline,
+            blit_sampler,
+            scaling_mode: ScalingMode::Fit,
+            color_space: ColorSpace::Srgb,
+            post_effect: PostEffect::None,
+            scanline_intensity: Self::DEFAULT_SCANLINE_INTENSITY,
+            crt_curvature: Self::DEFAULT_CRT_CURVATURE,
+            post_params_buffer,
+            post_params_bind_group,
+            buffer_pool: RefCell::new(BufferPool::new()),
+            capture_requests: Vec::new(),
+            texture_loads: Vec::new(),
+            stats: RenderStats::default(),
+        };
+        // Start at the canvas' real resolution instead of waiting for the
+        // first resize event. Drawing still happens in `width` x `height`.
+        // This is applied by the first render.
+        renderer.resize(window_size);
+        Ok(renderer)
     }
 
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.width > 0 && new_size.height > 0 {
-            let new_aspect_ratio = new_size.width as f32 / new_size.height as f32;
-            let (width, height) = if new_aspect_ratio > self.target_aspect_ratio {
-                (
-                    new_size.width,
-                    (new_size.width as f32 / self.target_aspect_ratio) as u32,
-                )
+    // Every draw pipeline has a depth attachment so they can share passes. In
+    // painter mode the test always passes and nothing is written.
+    fn create_draw_pipelines(
+        device: &Device,
+        shader: &wgpu::ShaderModule,
+        [flat_layout, instanced_layout, textured_layout]: &[wgpu::PipelineLayout; 3],
+        key: PipelineKey,
+        samples: Samples,
+    ) -> DrawPipelines {
+        let blend = key.blend_mode.state();
+        let depth_stencil = wgpu::DepthStencilState {
+            format: Self::DEPTH_FORMAT,
+            depth_write_enabled: key.depth_test,
+            depth_compare: if key.depth_test {
+                wgpu::CompareFunction::LessEqual
             } else {
-                (
-                    (new_size.height as f32 * self.target_aspect_ratio) as u32,
-                    new_size.height,
-                )
-            };
-            self.size = winit::dpi::PhysicalSize::new(width, height);
-            self.config.width = width;
-            self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
+                wgpu::CompareFunction::Always
+            },
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        };
+        let create = |label: &str,
+                      layout: &wgpu::PipelineLayout,
+                      vertex_entry: &str,
+                      fragment_entry: &str,
+                      buffers: &[wgpu::VertexBufferLayout]| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: Some(vertex_entry),
+                    buffers,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: Some(fragment_entry),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: key.format,
+                        blend: Some(blend),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: key.cull_backfaces.then_some(wgpu::Face::Back),
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: Some(depth_stencil.clone()),
+                multisample: wgpu::MultisampleState {
+                    count: samples.count(),
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        DrawPipelines {
+            flat: create(
+                "Render Pipeline",
+                flat_layout,
+                "vs_main",
+                "fs_main",
+                &[Vertex::desc()],
+            ),
+            instanced: create(
+                "Instanced Pipeline",
+                instanced_layout,
+                "vs_instanced",
+                "fs_instanced",
+                &[Vertex::desc(), InstanceRaw::desc()],
+            ),
+            textured: create(
+                "Textured Pipeline",
+                textured_layout,
+                "vs_textured",
+                "fs_textured",
+                &[TexturedVertex::desc()],
+            ),
         }
     }
 
-    pub fn canonical_resize(&mut self) {
-        self.resize(self.size);
+    fn create_depth_view(
+        device: &Device,
+        size: winit::dpi::PhysicalSize<u32>,
+        samples: Samples,
+    ) -> TextureView {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Texture"),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: samples.count(),
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
     }
 
-    pub fn create_vertex_buffer_internal(device: &Device, vertices: &[Vertex]) -> wgpu::Buffer {
-        let align = wgpu::COPY_BUFFER_ALIGNMENT as u64;
-        let vertex_size = (vertices.len() * std::mem::size_of::<Vertex>()) as u64;
-        let aligned_vertex_size = (vertex_size + align - 1) & !(align - 1);
-
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Vertex Buffer"),
-            size: aligned_vertex_size,
-            usage: wgpu::BufferUsages::VERTEX,
-            mapped_at_creation: true,
+    // The multisampled color target for `size`, none without multisampling
+    fn create_msaa_view(
+        device: &Device,
+        format: wgpu::TextureFormat,
+        size: winit::dpi::PhysicalSize<u32>,
+        samples: Samples,
+    ) -> Option<TextureView> {
+        if samples == Samples::One {
+            return None;
+        }
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Multisampled Color Texture"),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: samples.count(),
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
         });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
 
-        {
-            let mut buffer_view = vertex_buffer.slice(..).get_mapped_range_mut();
-            let vertex_bytes = bytemuck::cast_slice(vertices);
-            buffer_view[..vertex_bytes.len()].copy_from_slice(vertex_bytes);
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    /// The present modes the surface supports, `Fifo` (vsync) among them.
+    /// WebGL2 only ever offers `Fifo`, the browser paces frames itself, so
+    /// this only gives a choice on other backends.
+    pub fn supported_present_modes(&self) -> &[wgpu::PresentMode] {
+        &self.present_modes
+    }
+
+    /// Presents frames with `present_mode` from now on, and across resizes.
+    /// An unsupported mode is logged and falls back to `Fifo`, which every
+    /// surface supports. `AutoVsync` and `AutoNoVsync` always work, wgpu
+    /// picks the closest supported mode for them.
+    pub fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        let auto = matches!(
+            present_mode,
+            wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+        );
+        let present_mode = if auto || self.present_modes.contains(&present_mode) {
+            present_mode
+        } else {
+            log::warn!(
+                "The surface doesn't support {:?}, using Fifo instead",
+                present_mode
+            );
+            wgpu::PresentMode::Fifo
+        };
+        if present_mode == self.config.present_mode {
+            return;
         }
-        vertex_buffer.unmap();
+        self.config.present_mode = present_mode;
+        self.surface.configure(&self.device, &self.config);
+    }
 
-        vertex_buffer
+    pub fn msaa(&self) -> Samples {
+        self.msaa
     }
 
-    pub fn create_vertex_buffer(&self, vertices: &[Vertex]) -> wgpu::Buffer {
-        Self::create_vertex_buffer_internal(&self.device, vertices)
+    /// Draws with `samples` samples per pixel, resolved to a single sample
+    /// when the frame is presented or blitted. Rebuilds the targets, and the
+    /// pipelines as they're next drawn with, so switch it at startup or from
+    /// a settings menu, not per frame.
+    pub fn set_msaa(&mut self, samples: Samples) {
+        if samples == self.msaa {
+            return;
+        }
+        self.msaa = samples;
+        // Rebuilt with the new sample count as they're drawn with
+        self.pipelines.get_mut().clear();
+        self.depth_view = Self::create_depth_view(&self.device, self.size, samples);
+        self.msaa_view =
+            Self::create_msaa_view(&self.device, self.config.format, self.size, samples);
+        self.offscreen = Self::create_offscreen_target(
+            &self.device,
+            samples,
+            &self.texture_bind_group_layout,
+            &self.blit_sampler,
+            self.offscreen_format(),
+            self.original_size,
+        );
     }
 
-    pub fn create_index_buffer_internal(device: &Device, indices: &[u16]) -> wgpu::Buffer {
-        let align = wgpu::COPY_BUFFER_ALIGNMENT as u64;
-        let index_size = (indices.len() * std::mem::size_of::<u16>()) as u64;
-        let aligned_index_size = (index_size + align - 1) & !(align - 1);
+    pub fn depth_test(&self) -> bool {
+        self.depth_test
+    }
 
-        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("Index Buffer"),
-            size: aligned_index_size,
-            usage: wgpu::BufferUsages::INDEX,
-            mapped_at_creation: true,
-        });
+    /// With depth testing on, draws with a higher transform z end up in front
+    /// regardless of the order they were issued in. Off by default, so later
+    /// draws cover earlier ones, which is what 2D UI usually wants.
+    pub fn set_depth_test(&mut self, depth_test: bool) {
+        self.depth_test = depth_test;
+    }
 
-        {
+    fn draw_pipelines(&self, blend_mode: BlendMode, cull_backfaces: bool) -> DrawPipelines {
+        let key = PipelineKey {
+            blend_mode,
+            depth_test: self.depth_test,
+            cull_backfaces,
+            format: self.draw_format(),
+        };
+        self.pipelines
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| {
+                Self::create_draw_pipelines(
+                    &self.device,
+                    &self.shader,
+                    &self.draw_pipeline_layouts,
+                    key,
+                    self.msaa,
+                )
+            })
+            .clone()
+    }
+
+    fn create_blit_sampler(device: &Device, filter: wgpu::FilterMode) -> wgpu::Sampler {
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Blit Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        })
+    }
+
+    /// The GPU device, for building resources the engine doesn't provide.
+    ///
+    /// The renderer owns it; borrow it to create pipelines, buffers and
+    /// textures, but don't hold on to the reference past the frame. Anything
+    /// created from it stays valid for as long as the renderer lives.
+    /// Pipelines meant to draw into the engine's targets should target
+    /// `surface_format()` or `offscreen_format()`, whichever the frame is
+    /// drawn into, with a `DEPTH_FORMAT` depth attachment.
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// The queue the engine submits to. Work submitted here is ordered with
+    /// the engine's own submits, so writes made before `render` are visible
+    /// to the frame.
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    /// Color format of the surface.
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.config.format
+    }
+
+    /// Color format of the offscreen target: the sRGB variant of the surface
+    /// format, so blending happens in linear light even when the surface
+    /// itself isn't sRGB. The blit converts back for the surface.
+    pub fn offscreen_format(&self) -> wgpu::TextureFormat {
+        self.config.format.add_srgb_suffix()
+    }
+
+    // Format of the target this frame is drawn into
+    fn draw_format(&self) -> wgpu::TextureFormat {
+        if self.renders_offscreen() {
+            self.offscreen_format()
+        } else {
+            self.config.format
+        }
+    }
+
+    /// What the last `render` cost. Counters restart at every render.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    // `format` is offscreen_format, which the draw pipelines are also built for
+    fn create_offscreen_target(
+        device: &Device,
+        samples: Samples,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        format: wgpu::TextureFormat,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> OffscreenTarget {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Texture"),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Offscreen Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+
+        OffscreenTarget {
+            texture,
+            view,
+            msaa_view: Self::create_msaa_view(device, format, size, samples),
+            depth_view: Self::create_depth_view(device, size, samples),
+            bind_group,
+        }
+    }
+
+    pub fn render_target(&self) -> RenderTarget {
+        self.render_target
+    }
+
+    pub fn set_render_target(&mut self, render_target: RenderTarget) {
+        self.render_target = render_target;
+    }
+
+    pub fn post_effect(&self) -> PostEffect {
+        self.post_effect
+    }
+
+    /// Effects are applied while blitting, so any effect other than
+    /// `PostEffect::None` renders offscreen regardless of the render target.
+    pub fn set_post_effect(&mut self, post_effect: PostEffect) {
+        self.post_effect = post_effect;
+    }
+
+    /// How dark the gaps between scanlines get, from 0 (off) to 1 (black).
+    pub fn set_scanline_intensity(&mut self, intensity: f32) {
+        self.scanline_intensity = intensity.clamp(0.0, 1.0);
+    }
+
+    /// How strongly the CRT effect bends the image, 0 is flat.
+    pub fn set_crt_curvature(&mut self, curvature: f32) {
+        self.crt_curvature = curvature.max(0.0);
+    }
+
+    /// Changes how the offscreen texture is scaled up to the canvas. Nearest
+    /// is the default and keeps the low resolution output sharp.
+    pub fn set_blit_filter(&mut self, filter: wgpu::FilterMode) {
+        self.blit_sampler = Self::create_blit_sampler(&self.device, filter);
+        self.offscreen = Self::create_offscreen_target(
+            &self.device,
+            self.msaa,
+            &self.texture_bind_group_layout,
+            &self.blit_sampler,
+            self.offscreen_format(),
+            self.original_size,
+        );
+    }
+
+    pub fn scaling_mode(&self) -> ScalingMode {
+        self.scaling_mode
+    }
+
+    /// Only affects frames drawn offscreen, see `set_render_target`.
+    /// `IntegerNearest` also switches the blit filter to nearest.
+    pub fn set_scaling_mode(&mut self, scaling_mode: ScalingMode) {
+        self.scaling_mode = scaling_mode;
+        if scaling_mode == ScalingMode::IntegerNearest {
+            self.set_blit_filter(wgpu::FilterMode::Nearest);
+        }
+    }
+
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Picks how `EngineColor` values are read from now on, see `ColorSpace`.
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
+    }
+
+    // What to write for `color` so it shows as intended. sRGB targets expect
+    // linear values and encode them on write, plain ones store them as is.
+    fn surface_color(&self, color: EngineColor) -> EngineColor {
+        match (self.color_space, self.draw_format().is_srgb()) {
+            (ColorSpace::Srgb, true) => color.to_linear(),
+            (ColorSpace::Linear, false) => color.to_srgb(),
+            _ => color,
+        }
+    }
+
+    fn renders_offscreen(&self) -> bool {
+        self.render_target == RenderTarget::Offscreen
+            || self.post_effect != PostEffect::None
+            // The surface can't be copied from on WebGL, the offscreen texture can
+            || !self.capture_requests.is_empty()
+    }
+
+    /// Captures the next rendered frame as a PNG, at the target resolution and
+    /// before any post effect. The future resolves a frame or two after that
+    /// render, once the GPU has copied the image out, e.g.
+    /// `wasm_bindgen_futures::spawn_local(async move { let png = capture.await; ... })`.
+    pub fn capture_frame(&mut self) -> FrameCapture {
+        let slot = Rc::new(RefCell::new(CaptureSlot::default()));
+        self.capture_requests.push(Rc::clone(&slot));
+        FrameCapture { slot }
+    }
+
+    // Copies the finished offscreen frame into a buffer and hands it to the
+    // pending captures once it's mapped
+    fn read_back_offscreen(&mut self) {
+        let requests = mem::take(&mut self.capture_requests);
+        let format = self.offscreen_format();
+        let swap_red_blue = match format {
+            wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+            _ => {
+                log::error!("Can't capture frames in the {:?} format", format);
+                for slot in &requests {
+                    CaptureSlot::finish(slot, None);
+                }
+                return;
+            }
+        };
+
+        let width = self.original_size.width.max(1);
+        let height = self.original_size.height.max(1);
+        // Copies need every row padded to the alignment, stripped again below
+        let row_bytes = width * 4;
+        let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Capture Buffer"),
+            size: (padded_row_bytes * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Capture Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &self.offscreen.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let readback = buffer.clone();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let png = match result {
+                    Ok(()) => {
+                        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+                        {
+                            let mapped = readback.slice(..).get_mapped_range();
+                            for row in mapped.chunks_exact(padded_row_bytes as usize) {
+                                pixels.extend_from_slice(&row[..row_bytes as usize]);
+                            }
+                        }
+                        readback.unmap();
+                        if swap_red_blue {
+                            for pixel in pixels.chunks_exact_mut(4) {
+                                pixel.swap(0, 2);
+                            }
+                        }
+                        Self::encode_png(pixels, width, height)
+                    }
+                    Err(err) => {
+                        log::error!("Couldn't read the captured frame back: {:?}", err);
+                        None
+                    }
+                };
+                for slot in &requests {
+                    CaptureSlot::finish(slot, png.clone());
+                }
+            });
+    }
+
+    fn encode_png(rgba: Vec<u8>, width: u32, height: u32) -> Option<Vec<u8>> {
+        let image = image::RgbaImage::from_raw(width, height, rgba)?;
+        let mut png = std::io::Cursor::new(Vec::new());
+        match image.write_to(&mut png, image::ImageFormat::Png) {
+            Ok(()) => Some(png.into_inner()),
+            Err(err) => {
+                log::error!("Couldn't encode the captured frame: {:?}", err);
+                None
+            }
+        }
+    }
+
+    // Where the game lands on the surface, as `(offset, size)` in surface
+    // pixels. Only integer scaling of offscreen frames leaves borders.
+    fn blit_viewport(&self) -> (Vec2, Vec2) {
+        let surface = Vec2::new(self.config.width as f32, self.config.height as f32);
+        let native = Vec2::new(
+            self.original_size.width as f32,
+            self.original_size.height as f32,
+        );
+        let factor = (surface / native).min_element().floor();
+        if self.scaling_mode != ScalingMode::IntegerNearest
+            || !self.renders_offscreen()
+            || factor < 1.0
+        {
+            return (Vec2::ZERO, surface);
+        }
+        let size = native * factor;
+        (((surface - size) * 0.5).floor(), size)
+    }
+
+    // Copies the offscreen texture onto `view`, over the blit viewport
+    fn blit_offscreen(&mut self, view: &TextureView) {
+        let params = PostParamsRaw {
+            effect: match self.post_effect {
+                PostEffect::None => 0,
+                PostEffect::Scanlines => 1,
+                PostEffect::Crt => 2,
+            },
+            scanline_intensity: self.scanline_intensity,
+            curvature: self.crt_curvature,
+            rows: self.original_size.height as f32,
+            // The offscreen texture samples as linear, which a plain surface
+            // would store without encoding and show too dark
+            encode_srgb: (self.offscreen_format().is_srgb() && !self.config.format.is_srgb())
+                as u32,
+            _padding: [0; 3],
+        };
+        self.queue
+            .write_buffer(&self.post_params_buffer, 0, bytemuck::cast_slice(&[params]));
+        self.stats.buffer_writes += 1;
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Blit Encoder"),
+            });
+
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Blit Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            let (offset, size) = self.blit_viewport();
+            pass.set_viewport(offset.x, offset.y, size.x, size.y, 0.0, 1.0);
+            pass.set_pipeline(&self.blit_pipeline);
+            pass.set_bind_group(0, &self.transform_bind_group, &[0]);
+            pass.set_bind_group(1, &self.color_bind_group, &[0]);
+            pass.set_bind_group(2, &self.offscreen.bind_group, &[]);
+            pass.set_bind_group(3, &self.post_params_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.stats.draw_calls += 1;
+        self.stats.submits += 1;
+    }
+
+    pub fn circle_segments(&self) -> u32 {
+        self.circle_segments
+    }
+
+    /// Rebuilds the pre-baked circle with a different number of segments.
+    /// Large circles need more segments to look smooth.
+    pub fn set_circle_segments(&mut self, segments: u32) {
+        let (vertices, indices) = mesh::circle_geometry(segments);
+        self.circle_vertex_buffer = Self::create_vertex_buffer_internal(&self.device, &vertices);
+        self.circle_index_buffer = Self::create_index_buffer_internal(&self.device, &indices);
+        self.circle_segments = (indices.len() / 3) as u32;
+    }
+
+    /// Queues a resize to `new_size`. Browsers fire resize events in bursts,
+    /// so only the latest one is applied, once, at the start of the next
+    /// `render`.
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.width > 0 && new_size.height > 0 {
+            self.pending_size = Some(new_size);
+        }
+    }
+
+    fn apply_pending_resize(&mut self) {
+        if let Some(new_size) = self.pending_size.take() {
+            self.window_size = new_size;
+            let new_aspect_ratio = new_size.width as f32 / new_size.height as f32;
+            let (width, height) = if new_aspect_ratio > self.target_aspect_ratio {
+                (
+                    new_size.width,
+                    (new_size.width as f32 / self.target_aspect_ratio) as u32,
+                )
+            } else {
+                (
+                    (new_size.height as f32 * self.target_aspect_ratio) as u32,
+                    new_size.height,
+                )
+            };
+            self.size = winit::dpi::PhysicalSize::new(width, height);
+            self.config.width = width;
+            self.config.height = height;
+            self.surface.configure(&self.device, &self.config);
+            self.depth_view = Self::create_depth_view(&self.device, self.size, self.msaa);
+            self.msaa_view =
+                Self::create_msaa_view(&self.device, self.config.format, self.size, self.msaa);
+        }
+    }
+
+    /// Whether there is anything to draw into. A canvas that is collapsed to
+    /// nothing or inside a `display: none` container is not, and `render`
+    /// skips its frames until it comes back.
+    pub fn is_visible(&self) -> bool {
+        // Zero-sized resizes are dropped, so ask the element itself
+        self.canvas
+            .as_ref()
+            .is_none_or(|canvas| canvas.client_width() > 0 && canvas.client_height() > 0)
+    }
+
+    /// Reconfigures the surface at the current window size, for when it was
+    /// lost or went out of date. Like `resize`, this happens on the next render.
+    pub fn canonical_resize(&mut self) {
+        self.resize(self.window_size);
+    }
+
+    /// The clip-space matrix that applies `camera` on top of already projected
+    /// transforms, by conjugating its view matrix with the projection.
+    pub fn camera_matrix(&self, camera: &Camera2D) -> Mat4 {
+        let viewport = Vec2::new(
+            self.original_size.width as f32,
+            self.original_size.height as f32,
+        );
+        camera.clip_matrix(viewport, &self.ortographic_transform)
+    }
+
+    /// The area of the window the game occupies, as `(offset, size)` in window
+    /// pixels. The canvas is letterboxed to keep the target aspect ratio, and
+    /// integer scaling may add borders inside it, see `ScalingMode`.
+    pub fn content_rect(&self) -> (Vec2, Vec2) {
+        let window = Vec2::new(
+            self.window_size.width as f32,
+            self.window_size.height as f32,
+        );
+        let size = if window.x / window.y > self.target_aspect_ratio {
+            Vec2::new(window.y * self.target_aspect_ratio, window.y)
+        } else {
+            Vec2::new(window.x, window.x / self.target_aspect_ratio)
+        };
+        let offset = (window - size) * 0.5;
+
+        let surface = Vec2::new(self.config.width as f32, self.config.height as f32);
+        let (viewport_offset, viewport_size) = self.blit_viewport();
+        (
+            offset + viewport_offset / surface * size,
+            viewport_size / surface * size,
+        )
+    }
+
+    /// Converts a window position in physical pixels (such as
+    /// `InputSystem::mouse_position`) into the input space of `projection`,
+    /// undoing `camera` if one is given. Passing the size-invariant ortho gives
+    /// 0..1 game coordinates; passing `Transform::new()` gives clip space,
+    /// which is what `Transform::contains_point` expects for transforms that
+    /// already include their projection.
+    pub fn screen_to_world(
+        &self,
+        position: (f64, f64),
+        projection: &Transform,
+        camera: Option<&Camera2D>,
+    ) -> Vec3 {
+        let mut clip_from_world = projection.matrix();
+        if let Some(camera) = camera {
+            clip_from_world = self.camera_matrix(camera) * clip_from_world;
+        }
+        geometry::screen_to_world(
+            Vec2::new(position.0 as f32, position.1 as f32),
+            self.content_rect(),
+            clip_from_world,
+        )
+    }
+
+    pub fn create_vertex_buffer_internal(device: &Device, vertices: &[Vertex]) -> wgpu::Buffer {
+        let align = wgpu::COPY_BUFFER_ALIGNMENT as u64;
+        let vertex_size = (vertices.len() * std::mem::size_of::<Vertex>()) as u64;
+        let aligned_vertex_size = (vertex_size + align - 1) & !(align - 1);
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Vertex Buffer"),
+            size: aligned_vertex_size,
+            usage: wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: true,
+        });
+
+        {
+            let mut buffer_view = vertex_buffer.slice(..).get_mapped_range_mut();
+            let vertex_bytes = bytemuck::cast_slice(vertices);
+            buffer_view[..vertex_bytes.len()].copy_from_slice(vertex_bytes);
+        }
+        vertex_buffer.unmap();
+
+        vertex_buffer
+    }
+
+    pub fn create_vertex_buffer(&self, vertices: &[Vertex]) -> wgpu::Buffer {
+        Self::create_vertex_buffer_internal(&self.device, vertices)
+    }
+
+    pub fn create_textured_vertex_buffer_internal(
+        device: &Device,
+        vertices: &[TexturedVertex],
+    ) -> wgpu::Buffer {
+        let vertex_size = mem::size_of_val(vertices) as u64;
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Textured Vertex Buffer"),
+            size: vertex_size,
+            usage: wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: true,
+        });
+
+        {
+            let mut buffer_view = vertex_buffer.slice(..).get_mapped_range_mut();
+            buffer_view.copy_from_slice(bytemuck::cast_slice(vertices));
+        }
+        vertex_buffer.unmap();
+
+        vertex_buffer
+    }
+
+    /// Decodes a PNG or JPEG image into a texture. If decoding fails the error
+    /// is logged and a 1x1 magenta texture is returned instead, so draws using
+    /// it remain visible.
+    pub fn load_texture(&self, bytes: &[u8]) -> TextureHandle {
+        match image::load_from_memory(bytes) {
+            Ok(image) => {
+                let rgba = image.to_rgba8();
+                let (width, height) = rgba.dimensions();
+                self.create_texture_from_rgba(&rgba, width, height)
+            }
+            Err(err) => {
+                log::error!("Failed to decode texture: {:?}", err);
+                self.create_placeholder_texture()
+            }
+        }
+    }
+
+    fn create_placeholder_texture(&self) -> TextureHandle {
+        self.create_texture_from_rgba(&[255, 0, 255, 255], 1, 1)
+    }
+
+    /// Fetches an image and decodes it with the browser, without blocking.
+    /// Until it arrives the handle is a 1x1 magenta placeholder that can be
+    /// drawn as usual. If the fetch or decode fails the error is logged and
+    /// the placeholder stays.
+    pub fn load_texture_url(&mut self, url: &str) -> TextureHandle {
+        let handle = self.create_placeholder_texture();
+        handle.inner.borrow_mut().loading = true;
+
+        let state = Rc::new(RefCell::new(TextureLoadState::Loading));
+        let state_clone = state.clone();
+        let url = url.to_owned();
+        wasm_bindgen_futures::spawn_local(async move {
+            *state_clone.borrow_mut() = match Self::fetch_image_bitmap(&url).await {
+                Ok(bitmap) => TextureLoadState::Done(bitmap),
+                Err(err) => {
+                    log::error!("Failed to load texture {}: {:?}", url, err);
+                    TextureLoadState::Failed
+                }
+            };
+        });
+
+        self.texture_loads.push(PendingTexture {
+            state,
+            handle: handle.clone(),
+        });
+        handle
+    }
+
+    async fn fetch_image_bitmap(url: &str) -> Result<web_sys::ImageBitmap, JsValue> {
+        let window = web_sys::window().ok_or("No window")?;
+        let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url))
+            .await?
+            .dyn_into()?;
+        if !response.ok() {
+            return Err(format!("HTTP status {}", response.status()).into());
+        }
+        let blob: web_sys::Blob = JsFuture::from(response.blob()?).await?.dyn_into()?;
+        JsFuture::from(window.create_image_bitmap_with_blob(&blob)?)
+            .await?
+            .dyn_into()
+    }
+
+    // Uploads the images that arrived since the last frame into their handles
+    fn finish_texture_loads(&mut self) {
+        let mut loads = mem::take(&mut self.texture_loads);
+        loads.retain(|load| {
+            let state = mem::replace(&mut *load.state.borrow_mut(), TextureLoadState::Loading);
+            match state {
+                TextureLoadState::Loading => return true,
+                TextureLoadState::Done(bitmap) => {
+                    let size = wgpu::Extent3d {
+                        width: bitmap.width(),
+                        height: bitmap.height(),
+                        depth_or_array_layers: 1,
+                    };
+                    // Copies from external images render into the texture
+                    let texture = self.create_gpu_texture(
+                        size,
+                        wgpu::TextureUsages::TEXTURE_BINDING
+                            | wgpu::TextureUsages::COPY_DST
+                            | wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    );
+                    self.queue.copy_external_image_to_texture(
+                        &wgpu::CopyExternalImageSourceInfo {
+                            source: wgpu::ExternalImageSource::ImageBitmap(bitmap),
+                            origin: wgpu::Origin2d::ZERO,
+                            flip_y: false,
+                        },
+                        wgpu::CopyExternalImageDestInfo {
+                            texture: &texture.texture,
+                            mip_level: 0,
+                            origin: wgpu::Origin3d::ZERO,
+                            aspect: wgpu::TextureAspect::All,
+                            color_space: wgpu::PredefinedColorSpace::Srgb,
+                            premultiplied_alpha: false,
+                        },
+                        size,
+                    );
+                    *load.handle.inner.borrow_mut() = texture;
+                }
+                TextureLoadState::Failed => load.handle.inner.borrow_mut().loading = false,
+            }
+            false
+        });
+        self.texture_loads = loads;
+    }
+
+    /// Loads a glyph atlas image laid out as described by `BitmapFont`.
+    pub fn load_font(&self, bytes: &[u8]) -> BitmapFont {
+        BitmapFont::new(self.load_texture(bytes))
+    }
+
+    pub fn create_texture_from_rgba(&self, rgba: &[u8], width: u32, height: u32) -> TextureHandle {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.create_gpu_texture(
+            size,
+            wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        );
+
+        self.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            rgba,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        TextureHandle {
+            inner: Rc::new(RefCell::new(texture)),
+        }
+    }
+
+    fn create_gpu_texture(&self, size: wgpu::Extent3d, usage: wgpu::TextureUsages) -> GpuTexture {
+        // Images are sRGB. Only decode them when the target encodes again,
+        // otherwise texels would be written out as linear and look too dark.
+        // Textures keep their format, so this goes by the current target.
+        let texture_format = if self.draw_format().is_srgb() {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture_format,
+            usage,
+            view_formats: &[],
+        });
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Texture Bind Group"),
+            layout: &self.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.texture_sampler),
+                },
+            ],
+        });
+
+        GpuTexture {
+            texture,
+            bind_group,
+            loading: false,
+        }
+    }
+
+    pub fn create_instance_buffer_internal(
+        device: &Device,
+        instances: &[InstanceRaw],
+    ) -> wgpu::Buffer {
+        let instance_size = mem::size_of_val(instances) as u64;
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: instance_size,
+            usage: wgpu::BufferUsages::VERTEX,
+            mapped_at_creation: true,
+        });
+
+        {
+            let mut buffer_view = instance_buffer.slice(..).get_mapped_range_mut();
+            buffer_view.copy_from_slice(bytemuck::cast_slice(instances));
+        }
+        instance_buffer.unmap();
+
+        instance_buffer
+    }
+
+    pub fn create_index_buffer_internal(device: &Device, indices: &[u16]) -> wgpu::Buffer {
+        let align = wgpu::COPY_BUFFER_ALIGNMENT as u64;
+        let index_size = (indices.len() * std::mem::size_of::<u16>()) as u64;
+        let aligned_index_size = (index_size + align - 1) & !(align - 1);
+
+        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Index Buffer"),
+            size: aligned_index_size,
+            usage: wgpu::BufferUsages::INDEX,
+            mapped_at_creation: true,
+        });
+
+        {
             let mut buffer_view = index_buffer.slice(..).get_mapped_range_mut();
             let index_bytes = bytemuck::cast_slice(indices);
             buffer_view[..index_bytes.len()].copy_from_slice(index_bytes);
@@ -427,6 +2052,17 @@ impl RenderingSystem {
     }
 
     pub fn render(&mut self, game: &Game) -> Result<(), wgpu::SurfaceError> {
+        let performance = web_sys::window().and_then(|window| window.performance());
+        let start = performance.as_ref().map(|performance| performance.now());
+        self.stats = RenderStats::default();
+        // Runs the callbacks of finished buffer mappings, such as captures
+        let _ = self.device.poll(wgpu::PollType::Poll);
+        self.finish_texture_loads();
+        self.apply_pending_resize();
+        if !self.is_visible() {
+            return Ok(()); // Hidden, so there's nothing to present
+        }
+
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -473,20 +2109,56 @@ impl RenderingSystem {
         //    //}
         //}
 
-        let mut drawer = Drawer::new(self, &view);
+        let offscreen = self.renders_offscreen();
+        let (target_view, msaa_view, depth_view) = if offscreen {
+            (
+                &self.offscreen.view,
+                self.offscreen.msaa_view.as_ref(),
+                &self.offscreen.depth_view,
+            )
+        } else {
+            (&view, self.msaa_view.as_ref(), &self.depth_view)
+        };
+        // Multisampled frames are drawn into the msaa target and resolved
+        let (color_view, resolve_target) = match msaa_view {
+            Some(msaa_view) => (msaa_view, Some(target_view)),
+            None => (target_view, None),
+        };
+        let mut drawer = Drawer::new(self, color_view, resolve_target, depth_view);
+        // Games that never clear would otherwise test against stale depth
+        if self.depth_test {
+            drawer.clear_depth();
+        }
 
         game.render(&mut drawer);
 
         drawer.flush();
+        self.stats = drawer.stats();
+        // Everything drawn this frame has been submitted now
+        let pool = self.buffer_pool.get_mut();
+        pool.reclaim();
+        (self.stats.buffers_reused, self.stats.buffers_created) = pool.take_stats();
+
+        if !self.capture_requests.is_empty() {
+            self.read_back_offscreen();
+        }
+        if offscreen {
+            self.blit_offscreen(&view);
+        }
 
         //self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
 
+        if let (Some(performance), Some(start)) = (performance, start) {
+            self.stats.frame_time_ms = performance.now() - start;
+        }
         Ok(())
     }
 }
 
 impl<'a> Drawer<'a> {
+    pub const DEBUG_LINE_THICKNESS: f32 = 0.005;
+
     //pub fn draw_geometry(
     //    &mut self,
     //    vertex_buffer: &wgpu::Buffer,
@@ -499,58 +2171,180 @@ impl<'a> Drawer<'a> {
     //    self.pass.draw_indexed(0..num_indices, 0, 0..1);
     //}
 
-    pub fn new(renderer: &'a RenderingSystem, view: &'a TextureView) -> Self {
+    pub fn new(
+        renderer: &'a RenderingSystem,
+        view: &'a TextureView,
+        resolve_target: Option<&'a TextureView>,
+        depth_view: &'a TextureView,
+    ) -> Self {
         Self {
             renderer,
             view,
-            command_buffers: Vec::new(),
+            resolve_target,
+            depth_view,
+            commands: Vec::new(),
+            transforms: Vec::new(),
+            colors: Vec::new(),
+            transform: renderer.ortographic_transform.clone(),
+            color: EngineColor::WHITE,
+            blend_mode: BlendMode::Alpha,
+            backface_culling: true,
+            transparency_sort: false,
+            layer: 0,
+            stats: RenderStats::default(),
             ortho: &renderer.ortographic_transform,
+            batch: Vec::new(),
+            camera: None,
+        }
+    }
+
+    /// The work this drawer has done so far. A frame only takes more than
+    /// one submit if it has more slow draws than there are uniform slots.
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    /// Makes subsequent draws go through `camera`. Transforms handed to the
+    /// drawer already include their projection, so the camera is applied in
+    /// clip space.
+    pub fn set_camera(&mut self, camera: &Camera2D) {
+        self.camera = Some(Transform::from_matrix(self.renderer.camera_matrix(camera)));
+    }
+
+    pub fn clear_camera(&mut self) {
+        self.camera = None;
+    }
+
+    /// Runs `f` with the camera disabled, for HUD elements that must stay
+    /// fixed on screen.
+    pub fn with_screen_space<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let camera = self.camera.take();
+        let result = f(self);
+        self.camera = camera;
+        result
+    }
+
+    fn view_transform(&self, transform: &Transform) -> Transform {
+        match &self.camera {
+            Some(camera) => camera.compose(transform),
+            None => transform.clone(),
         }
     }
 
     fn apply_transform(&mut self, transform: &Transform) {
-        // we need to flush or else it will be out of order
-        self.flush();
-        transform.write_buffer(&self.renderer.transform_buffer, &self.renderer.queue);
+        self.transform = self.view_transform(transform);
     }
 
     pub fn clear_slow(&mut self, color: Color) {
-        let mut encoder =
-            self.renderer
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Gizmo Encoder"),
-                });
+        // Pending batched squares were drawn before the clear
+        self.encode_batch();
+        self.commands.push(DrawCommand::Clear(Some(color)));
+    }
 
-        {
-            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Gizmo Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: self.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(color),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-        }
-        //self.renderer
-        //    .queue
-        //    .submit(std::iter::once(encoder.finish()));
-        self.command_buffers.push(encoder.finish());
+    /// Resets the depth buffer so following draws only test against each other.
+    pub fn clear_depth(&mut self) {
+        self.encode_batch();
+        self.commands.push(DrawCommand::Clear(None));
+    }
+
+    /// Fills the whole target with `color`, matching how draws of the same
+    /// color look.
+    pub fn clear(&mut self, color: EngineColor) {
+        self.clear_slow(self.renderer.surface_color(color).into());
     }
 
     pub fn set_color(&mut self, color: EngineColor) {
-        self.flush();
-        self.renderer.queue.write_buffer(
-            &self.renderer.color_buffer,
-            0,
-            bytemuck::cast_slice(&[color]),
-        );
+        self.color = color;
+    }
+
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    /// Picks the blend pipelines for subsequent draws. Each recorded draw
+    /// keeps its own mode, so switching doesn't cost a submit.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        if blend_mode != self.blend_mode {
+            // Squares queued so far were meant to use the old mode
+            self.encode_batch();
+            self.blend_mode = blend_mode;
+        }
+    }
+
+    pub fn backface_culling(&self) -> bool {
+        self.backface_culling
+    }
+
+    pub fn transparency_sort(&self) -> bool {
+        self.transparency_sort
+    }
+
+    /// Draws the squares of each `BlendMode::Alpha` batch back to front, by
+    /// the z of their transforms, so overlapping translucent squares blend
+    /// in the right order whatever order they were queued in. Off by
+    /// default. Opaque draws don't need it, and neither do additive and
+    /// multiplied ones, which come out the same in any order.
+    pub fn set_transparency_sort(&mut self, enabled: bool) {
+        if enabled != self.transparency_sort {
+            self.encode_batch();
+            self.transparency_sort = enabled;
+        }
+    }
+
+    /// Triangles facing away are skipped while this is on, the default. Slow
+    /// draws through a mirrored transform (see `Transform::is_mirrored`) are
+    /// always drawn double-sided, but batched squares only have one setting
+    /// for the whole batch, so turn this off to batch mirrored ones.
+    pub fn set_backface_culling(&mut self, enabled: bool) {
+        if enabled != self.backface_culling {
+            self.encode_batch();
+            self.backface_culling = enabled;
+        }
+    }
+
+    pub fn layer(&self) -> i32 {
+        self.layer
+    }
+
+    /// Puts subsequent draws on `layer`. On flush, draws on higher layers are
+    /// drawn over lower ones whatever order they were issued in, and draws
+    /// on the same layer keep their order. Layers only sort draws between
+    /// two clears, and within one submit. Everything starts on layer 0.
+    pub fn set_layer(&mut self, layer: i32) {
+        if layer != self.layer {
+            // Squares queued so far belong to the old layer
+            self.encode_batch();
+            self.layer = layer;
+        }
+    }
+
+    // Records a slow draw with the current transform and color
+    fn push_geometry(
+        &mut self,
+        textured: Option<BindGroup>,
+        vertex_buffer: &Buffer,
+        index_buffer: &Buffer,
+        num_indices: u32,
+    ) {
+        // Keep the draw order with squares batched so far
+        self.encode_batch();
+        if self.transforms.len() == RenderingSystem::MAX_DRAWS_PER_SUBMIT as usize {
+            self.flush(); // Out of uniform slots, submit what we have
+        }
+        let slot = self.transforms.len() as u32;
+        self.transforms.push(self.transform.as_raw());
+        self.colors.push(self.renderer.surface_color(self.color));
+        self.commands.push(DrawCommand::Geometry {
+            layer: self.layer,
+            blend_mode: self.blend_mode,
+            // Mirroring flips the winding, so the front would be culled
+            cull_backfaces: self.backface_culling && !self.transform.is_mirrored(),
+            textured,
+            vertex_buffer: vertex_buffer.clone(),
+            index_buffer: index_buffer.clone(),
+            num_indices,
+            slot,
+        });
     }
 
     pub fn draw_geometry_slow(
@@ -561,73 +2355,488 @@ impl<'a> Drawer<'a> {
         transform: Option<&Transform>,
         color: Option<&EngineColor>,
     ) {
-        if let Some(t) = transform {
-            self.apply_transform(t);
-        } else {
-            self.apply_transform(self.ortho);
+        self.apply_transform(transform.unwrap_or(self.ortho));
+        self.set_color(color.copied().unwrap_or(EngineColor::WHITE));
+        self.push_geometry(None, vertex_buffer, index_buffer, num_indices);
+    }
+
+    /// Draws the unit square with its own color at each corner, blended
+    /// across it, for gradients and vignettes. Corners go top left, bottom
+    /// left, bottom right, top right, like the prebaked square.
+    pub fn draw_gradient_square(
+        &mut self,
+        transform: Option<&Transform>,
+        corner_colors: [EngineColor; 4],
+    ) {
+        let vertices = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]]
+            .into_iter()
+            .zip(corner_colors)
+            .map(|(position, color)| {
+                let color = self.renderer.surface_color(color);
+                Vertex {
+                    position: [position[0], position[1], 0.0],
+                    color: [color.r, color.g, color.b, color.a],
+                }
+            })
+            .collect::<Vec<_>>();
+        let vertex_buffer =
+            self.upload(wgpu::BufferUsages::VERTEX, bytemuck::cast_slice(&vertices));
+        self.draw_geometry_slow(
+            &vertex_buffer,
+            &self.renderer.square_index_buffer,
+            6, // 6 indices for the square
+            transform,
+            None,
+        );
+    }
+
+    pub fn draw_square_slow(&mut self, transform: Option<&Transform>, color: Option<&EngineColor>) {
+        self.draw_geometry_slow(
+            &self.renderer.square_vertex_buffer,
+            &self.renderer.square_index_buffer,
+            6, // 6 indices for the square
+            transform,
+            color,
+        );
+    }
+
+    /// Fills a `w` x `h` rectangle at pixel coordinates of the game's target
+    /// size, the space of `ortho`: the origin is the top left corner of the
+    /// game, y points down, and `x`, `y` is the rectangle's top left corner.
+    /// Saves composing a transform for simple HUD elements.
+    pub fn fill_rect_px(&mut self, x: f32, y: f32, w: f32, h: f32, color: EngineColor) {
+        let transform = self
+            .ortho
+            .translate(Vec3::new(x, y, 0.0))
+            .scale(Vec3::new(w, h, 1.0));
+        self.draw_square_slow(Some(&transform), Some(&color));
+    }
+
+    pub fn draw_circle_slow(&mut self, transform: Option<&Transform>, color: Option<&EngineColor>) {
+        self.draw_geometry_slow(
+            &self.renderer.circle_vertex_buffer,
+            &self.renderer.circle_index_buffer,
+            self.renderer.circle_segments * 3, // 3 indices per fan triangle
+            transform,
+            color,
+        );
+    }
+
+    /// Like `draw_circle_slow`, with the fan split into `segments` triangles
+    /// instead of `RenderingSystem::circle_segments`. Around 12 are plenty
+    /// for small circles, large ones want 64 or more. The fans for the first
+    /// few counts drawn are kept, others are rebuilt on every draw.
+    pub fn draw_circle_segments(
+        &mut self,
+        transform: Option<&Transform>,
+        color: Option<&EngineColor>,
+        segments: u32,
+    ) {
+        let renderer = self.renderer;
+        if segments == renderer.circle_segments {
+            self.draw_circle_slow(transform, color);
+            return;
         }
-        if let Some(c) = color {
-            self.set_color(*c);
-        } else {
-            self.set_color(EngineColor {
-                r: 1.0,
-                g: 1.0,
-                b: 1.0,
-                a: 1.0,
-            });
+
+        let cached = renderer.circle_cache.borrow().get(&segments).map(|circle| {
+            (
+                circle.vertex_buffer.clone(),
+                circle.index_buffer.clone(),
+                circle.num_indices,
+            )
+        });
+        let (vertex_buffer, index_buffer, num_indices) = match cached {
+            Some(circle) => circle,
+            None => {
+                let (vertices, indices) = mesh::circle_geometry(segments);
+                let num_indices = indices.len() as u32;
+                let mut cache = renderer.circle_cache.borrow_mut();
+                if cache.len() < RenderingSystem::CIRCLE_CACHE_SIZE {
+                    let vertex_buffer =
+                        RenderingSystem::create_vertex_buffer_internal(&renderer.device, &vertices);
+                    let index_buffer =
+                        RenderingSystem::create_index_buffer_internal(&renderer.device, &indices);
+                    cache.insert(
+                        segments,
+                        CircleGeometry {
+                            vertex_buffer: vertex_buffer.clone(),
+                            index_buffer: index_buffer.clone(),
+                            num_indices,
+                        },
+                    );
+                    (vertex_buffer, index_buffer, num_indices)
+                } else {
+                    drop(cache);
+                    (
+                        self.upload(wgpu::BufferUsages::VERTEX, bytemuck::cast_slice(&vertices)),
+                        self.upload(wgpu::BufferUsages::INDEX, bytemuck::cast_slice(&indices)),
+                        num_indices,
+                    )
+                }
+            }
+        };
+        self.draw_geometry_slow(&vertex_buffer, &index_buffer, num_indices, transform, color);
+    }
+
+    /// Draws the convex polygon through `points`, given in the local space of
+    /// `transform`. See `mesh::polygon_geometry`.
+    pub fn draw_polygon(
+        &mut self,
+        points: &[Vec2],
+        transform: Option<&Transform>,
+        color: Option<&EngineColor>,
+    ) {
+        let (vertices, indices) = mesh::polygon_geometry(points);
+        if indices.is_empty() {
+            return;
         }
-        let mut encoder =
-            self.renderer
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Gizmo Encoder"),
-                });
+        let vertex_buffer =
+            self.upload(wgpu::BufferUsages::VERTEX, bytemuck::cast_slice(&vertices));
+        let index_buffer = self.upload(wgpu::BufferUsages::INDEX, bytemuck::cast_slice(&indices));
+        self.draw_geometry_slow(
+            &vertex_buffer,
+            &index_buffer,
+            indices.len() as u32,
+            transform,
+            color,
+        );
+    }
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Gizmo Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: self.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
+    /// Draws a line as a thin quad. `a`, `b` and `thickness` are in the 0..1
+    /// size-invariant game space, like the Pong objects.
+    pub fn draw_line(&mut self, a: Vec2, b: Vec2, color: EngineColor, thickness: f32) {
+        let delta = b - a;
+        let length = delta.length();
+        if length <= f32::EPSILON {
+            return;
+        }
+
+        let transform = Transform::ortographic_size_invariant()
+            .translate(a.extend(0.0))
+            .rotate(delta.y.atan2(delta.x), Vec3::Z)
+            .translate(Vec3::new(0.0, -thickness * 0.5, 0.0))
+            .scale(Vec3::new(length, thickness, 1.0));
+        self.draw_square_batched(Some(&transform), Some(&color));
+    }
 
-            render_pass.set_pipeline(&self.renderer.render_pipeline);
-            render_pass.set_bind_group(0, &self.renderer.transform_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.renderer.color_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
-            render_pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-            render_pass.draw_indexed(0..num_indices, 0, 0..1);
+    /// Outlines the quad `transform` maps the unit square to.
+    pub fn draw_wireframe_square(&mut self, transform: &Transform, color: EngineColor) {
+        // Bring the projected corners back into game space for draw_line
+        let to_game = Transform::ortographic_size_invariant().matrix().inverse();
+        let corners = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+        ]
+        .map(|corner| {
+            to_game
+                .transform_point3(transform.project(corner))
+                .truncate()
+        });
+
+        for i in 0..4 {
+            self.draw_line(
+                corners[i],
+                corners[(i + 1) % 4],
+                color,
+                Self::DEBUG_LINE_THICKNESS,
+            );
         }
-        //self.renderer
-        //    .queue
-        //    .submit(std::iter::once(encoder.finish()));
-        self.command_buffers.push(encoder.finish());
     }
 
-    pub fn draw_square_slow(&mut self, transform: Option<&Transform>, color: Option<&EngineColor>) {
-        self.draw_geometry_slow(
-            &self.renderer.square_vertex_buffer,
+    /// Queues a square to be drawn with a single instanced draw call on the
+    /// next flush. Unlike the slow path, this does not touch the shared
+    /// transform and color uniforms.
+    pub fn draw_square_batched(
+        &mut self,
+        transform: Option<&Transform>,
+        color: Option<&EngineColor>,
+    ) {
+        let transform = self.view_transform(transform.unwrap_or(self.ortho));
+        let color = self
+            .renderer
+            .surface_color(color.copied().unwrap_or(EngineColor::WHITE));
+        self.batch.push(InstanceRaw {
+            model: transform.as_raw(),
+            color,
+        });
+    }
+
+    /// Draws every square in `instances` with one instanced draw call, for
+    /// walls and grids of identical squares. Squares already queued with
+    /// `draw_square_batched` go out in the same call, before these.
+    pub fn draw_squares(&mut self, instances: &[(Transform, EngineColor)]) {
+        for (transform, color) in instances {
+            self.draw_square_batched(Some(transform), Some(color));
+        }
+        self.encode_batch();
+    }
+
+    // A transient buffer from the renderer's pool, only valid for this frame
+    fn upload(&mut self, usage: wgpu::BufferUsages, contents: &[u8]) -> Buffer {
+        let renderer = self.renderer;
+        renderer
+            .buffer_pool
+            .borrow_mut()
+            .upload(&renderer.device, &renderer.queue, usage, contents)
+    }
+
+    fn encode_batch(&mut self) {
+        if self.batch.is_empty() {
+            return;
+        }
+
+        let mut instances = mem::take(&mut self.batch);
+        if self.transparency_sort && self.blend_mode == BlendMode::Alpha {
+            InstanceRaw::sort_back_to_front(&mut instances);
+        }
+        let instance_buffer =
+            self.upload(wgpu::BufferUsages::VERTEX, bytemuck::cast_slice(&instances));
+        self.commands.push(DrawCommand::Batch {
+            layer: self.layer,
+            blend_mode: self.blend_mode,
+            cull_backfaces: self.backface_culling,
+            instance_buffer,
+            count: instances.len() as u32,
+        });
+    }
+
+    pub fn draw_textured_square(
+        &mut self,
+        transform: Option<&Transform>,
+        texture: &TextureHandle,
+        tint: Option<&EngineColor>,
+    ) {
+        self.draw_textured_geometry_slow(
+            &self.renderer.textured_square_vertex_buffer,
+            &self.renderer.square_index_buffer,
+            6, // 6 indices for the square
+            transform,
+            texture,
+            tint,
+        );
+    }
+
+    /// Draws the `uv_min`..`uv_max` region of `texture` stretched over the
+    /// unit square, e.g. one frame of a sprite sheet from `TextureAtlas`.
+    pub fn draw_textured_square_uv(
+        &mut self,
+        transform: Option<&Transform>,
+        texture: &TextureHandle,
+        uv_min: Vec2,
+        uv_max: Vec2,
+        tint: Option<&EngineColor>,
+    ) {
+        let vertices = [
+            ([0.0, 0.0], [uv_min.x, uv_min.y]), // Top Left
+            ([0.0, 1.0], [uv_min.x, uv_max.y]), // Bottom Left
+            ([1.0, 1.0], [uv_max.x, uv_max.y]), // Bottom Right
+            ([1.0, 0.0], [uv_max.x, uv_min.y]), // Top Right
+        ]
+        .map(|(position, uv)| TexturedVertex {
+            position: [position[0], position[1], 0.0],
+            color: [1.0, 1.0, 1.0],
+            uv,
+        });
+        let vertex_buffer =
+            self.upload(wgpu::BufferUsages::VERTEX, bytemuck::cast_slice(&vertices));
+        self.draw_textured_geometry_slow(
+            &vertex_buffer,
             &self.renderer.square_index_buffer,
             6, // 6 indices for the square
             transform,
+            texture,
+            tint,
+        );
+    }
+
+    /// Draws `text` with one glyph per unit cell of `transform`'s local space,
+    /// so the transform's scale sets the glyph size. Lines are split on `\n`.
+    pub fn draw_text(
+        &mut self,
+        text: &str,
+        font: &BitmapFont,
+        transform: Option<&Transform>,
+        color: Option<&EngineColor>,
+    ) {
+        let (vertices, indices) = BitmapFont::layout(text);
+        if indices.is_empty() {
+            return;
+        }
+        let vertex_buffer =
+            self.upload(wgpu::BufferUsages::VERTEX, bytemuck::cast_slice(&vertices));
+        let index_buffer = self.upload(wgpu::BufferUsages::INDEX, bytemuck::cast_slice(&indices));
+        self.draw_textured_geometry_slow(
+            &vertex_buffer,
+            &index_buffer,
+            indices.len() as u32,
+            transform,
+            &font.texture,
             color,
         );
     }
 
+    pub fn draw_textured_geometry_slow(
+        &mut self,
+        vertex_buffer: &Buffer,
+        index_buffer: &Buffer,
+        num_indices: u32,
+        transform: Option<&Transform>,
+        texture: &TextureHandle,
+        tint: Option<&EngineColor>,
+    ) {
+        self.apply_transform(transform.unwrap_or(self.ortho));
+        self.set_color(tint.copied().unwrap_or(EngineColor::WHITE));
+        self.push_geometry(
+            Some(texture.bind_group()),
+            vertex_buffer,
+            index_buffer,
+            num_indices,
+        );
+    }
+
+    // Starts a pass over the drawer's targets. The pass is detached from the
+    // encoder's lifetime so a new one can replace it inside the encode loop.
+    fn begin_pass(
+        &self,
+        encoder: &mut CommandEncoder,
+        color: wgpu::LoadOp<Color>,
+        depth: wgpu::LoadOp<f32>,
+    ) -> RenderPass<'static> {
+        encoder
+            .begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Drawer Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: self.view,
+                    resolve_target: self.resolve_target,
+                    ops: wgpu::Operations {
+                        load: color,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: self.depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: depth,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            })
+            .forget_lifetime()
+    }
+
     pub fn flush(&mut self) {
-        if !self.command_buffers.is_empty() {
-            self.renderer
+        self.encode_batch();
+        if self.commands.is_empty() {
+            return;
+        }
+
+        // Upload every slot in one write per buffer, into the next page
+        let renderer = self.renderer;
+        let stride = renderer.uniform_stride as usize;
+        let page = renderer.uniform_page.get();
+        let page_offset =
+            page as u64 * RenderingSystem::MAX_DRAWS_PER_SUBMIT as u64 * stride as u64;
+        if !self.transforms.is_empty() {
+            renderer
+                .uniform_page
+                .set((page + 1) % RenderingSystem::UNIFORM_PAGES);
+            let mut transforms = vec![0u8; stride * self.transforms.len()];
+            let mut colors = vec![0u8; stride * self.colors.len()];
+            for (slot, (transform, color)) in self.transforms.iter().zip(&self.colors).enumerate() {
+                let transform = bytemuck::bytes_of(transform);
+                let color = bytemuck::bytes_of(color);
+                transforms[slot * stride..][..transform.len()].copy_from_slice(transform);
+                colors[slot * stride..][..color.len()].copy_from_slice(color);
+            }
+            renderer
                 .queue
-                .submit(mem::take(&mut self.command_buffers));
-            self.command_buffers.clear();
+                .write_buffer(&renderer.transform_buffer, page_offset, &transforms);
+            renderer
+                .queue
+                .write_buffer(&renderer.color_buffer, page_offset, &colors);
+            self.stats.buffer_writes += 2;
         }
+
+        let mut encoder = renderer
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Drawer Encoder"),
+            });
+
+        let mut commands = mem::take(&mut self.commands);
+        DrawCommand::sort_by_layer(&mut commands);
+        let mut pass: Option<RenderPass<'static>> = None;
+        for command in commands {
+            if let DrawCommand::Clear(color) = command {
+                drop(pass.take());
+                let color = color.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear);
+                pass = Some(self.begin_pass(&mut encoder, color, wgpu::LoadOp::Clear(1.0)));
+                continue;
+            }
+            let pass = pass.get_or_insert_with(|| {
+                self.begin_pass(&mut encoder, wgpu::LoadOp::Load, wgpu::LoadOp::Load)
+            });
+            match command {
+                DrawCommand::Clear(_) => unreachable!(),
+                DrawCommand::Geometry {
+                    layer: _,
+                    blend_mode,
+                    cull_backfaces,
+                    textured,
+                    vertex_buffer,
+                    index_buffer,
+                    num_indices,
+                    slot,
+                } => {
+                    let offset = (page_offset + slot as u64 * renderer.uniform_stride) as u32;
+                    let pipelines = renderer.draw_pipelines(blend_mode, cull_backfaces);
+                    match &textured {
+                        Some(texture) => {
+                            pass.set_pipeline(&pipelines.textured);
+                            pass.set_bind_group(2, texture, &[]);
+                        }
+                        None => pass.set_pipeline(&pipelines.flat),
+                    }
+                    pass.set_bind_group(0, &renderer.transform_bind_group, &[offset]);
+                    pass.set_bind_group(1, &renderer.color_bind_group, &[offset]);
+                    pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    pass.set_index_buffer(index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                    pass.draw_indexed(0..num_indices, 0, 0..1);
+                    self.stats.draw_calls += 1;
+                }
+                DrawCommand::Batch {
+                    layer: _,
+                    blend_mode,
+                    cull_backfaces,
+                    instance_buffer,
+                    count,
+                } => {
+                    pass.set_pipeline(
+                        &renderer
+                            .draw_pipelines(blend_mode, cull_backfaces)
+                            .instanced,
+                    );
+                    pass.set_vertex_buffer(0, renderer.square_vertex_buffer.slice(..));
+                    pass.set_vertex_buffer(1, instance_buffer.slice(..));
+                    pass.set_index_buffer(
+                        renderer.square_index_buffer.slice(..),
+                        wgpu::IndexFormat::Uint16,
+                    );
+                    pass.draw_indexed(0..6, 0, 0..count);
+                    self.stats.draw_calls += 1;
+                }
+            }
+        }
+        drop(pass);
+
+        renderer.queue.submit(std::iter::once(encoder.finish()));
+        self.stats.submits += 1;
+        self.transforms.clear();
+        self.colors.clear();
     }
 }
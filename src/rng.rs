@@ -0,0 +1,78 @@
+/// A small seedable xorshift generator. Not suitable for anything security
+/// related, but the same seed always gives the same sequence, on any target.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // Scramble the seed with splitmix64 so nearby seeds diverge quickly and
+        // zero, which xorshift can't leave, isn't reachable
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Self { state: z.max(1) }
+    }
+
+    /// A seed from the page's high resolution clock, so every load plays
+    /// differently. Avoids pulling in an entropy source that needs JS glue.
+    #[cfg(target_arch = "wasm32")]
+    pub fn time_seed() -> u64 {
+        web_sys::window()
+            .and_then(|window| window.performance())
+            .map(|performance| performance.now().to_bits())
+            .unwrap_or(0)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Uniform in `0.0..1.0`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `min..max`.
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// A fair coin flip.
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u64() >> 63 == 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_the_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        assert_ne!(Rng::new(1).next_u64(), Rng::new(2).next_u64());
+    }
+
+    #[test]
+    fn range_stays_in_bounds() {
+        let mut rng = Rng::new(0);
+        for _ in 0..1000 {
+            let value = rng.range(-2.0, 3.0);
+            assert!((-2.0..3.0).contains(&value));
+        }
+    }
+}
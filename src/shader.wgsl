@@ -1,11 +1,11 @@
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) color: vec3<f32>,
+    @location(1) color: vec4<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec3<f32>,
+    @location(0) color: vec4<f32>,
 }
 
 struct Transform {
@@ -32,5 +32,133 @@ var<uniform> engine_color: EngineColor;
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0) * engine_color.color;
-}
\ No newline at end of file
+    // Vertex colors tint the draw's color, white vertices leave it as is
+    return in.color * engine_color.color;
+}
+
+struct InstanceInput {
+    @location(2) model_0: vec4<f32>,
+    @location(3) model_1: vec4<f32>,
+    @location(4) model_2: vec4<f32>,
+    @location(5) model_3: vec4<f32>,
+    @location(6) color: vec4<f32>,
+}
+
+struct InstancedVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_instanced(model: VertexInput, instance: InstanceInput) -> InstancedVertexOutput {
+    let matrix = mat4x4<f32>(
+        instance.model_0,
+        instance.model_1,
+        instance.model_2,
+        instance.model_3,
+    );
+    var out: InstancedVertexOutput;
+    out.color = model.color * instance.color;
+    out.clip_position = matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_instanced(in: InstancedVertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+
+struct TexturedVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+}
+
+struct TexturedVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+}
+
+@group(2) @binding(0)
+var t_diffuse: texture_2d<f32>;
+@group(2) @binding(1)
+var s_diffuse: sampler;
+
+@vertex
+fn vs_textured(model: TexturedVertexInput) -> TexturedVertexOutput {
+    var out: TexturedVertexOutput;
+    out.color = model.color;
+    out.uv = model.uv;
+    out.clip_position = transform.matrix * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_textured(in: TexturedVertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(t_diffuse, s_diffuse, in.uv);
+    return texel * vec4<f32>(in.color, 1.0) * engine_color.color;
+}
+
+struct BlitVertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen, clipped to the viewport
+@vertex
+fn vs_blit(@builtin(vertex_index) index: u32) -> BlitVertexOutput {
+    let position = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u)) * 2.0 - 1.0;
+    var out: BlitVertexOutput;
+    out.clip_position = vec4<f32>(position, 0.0, 1.0);
+    out.uv = vec2<f32>(position.x + 1.0, 1.0 - position.y) * 0.5;
+    return out;
+}
+
+struct PostParams {
+    // 0 = none, 1 = scanlines, 2 = crt
+    effect: u32,
+    scanline_intensity: f32,
+    curvature: f32,
+    rows: f32,
+    // 1 when the surface isn't sRGB, so the blit encodes the linear texels
+    encode_srgb: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+}
+
+@group(3) @binding(0)
+var<uniform> post: PostParams;
+
+// The sRGB transfer function, what an sRGB target applies on write
+fn linear_to_srgb(linear: vec3<f32>) -> vec3<f32> {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_blit(in: BlitVertexOutput) -> @location(0) vec4<f32> {
+    var uv = in.uv;
+    if post.effect == 2u {
+        // Barrel distortion, pushing the corners outwards
+        let centered = uv * 2.0 - 1.0;
+        uv = centered * (1.0 + post.curvature * centered.yx * centered.yx) * 0.5 + 0.5;
+    }
+
+    var color = textureSample(t_diffuse, s_diffuse, uv);
+
+    if post.effect != 0u {
+        // Bright at the center of each game pixel row, dark between rows
+        let line = 0.5 - 0.5 * cos(uv.y * post.rows * 6.28318530718);
+        color = vec4<f32>(color.rgb * mix(1.0, line, post.scanline_intensity), color.a);
+    }
+    if post.effect == 2u && (any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0))) {
+        color = vec4<f32>(0.0, 0.0, 0.0, 1.0); // Outside the curved screen
+    }
+    if post.encode_srgb == 1u {
+        color = vec4<f32>(linear_to_srgb(color.rgb), color.a);
+    }
+    return color;
+}
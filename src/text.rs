@@ -0,0 +1,88 @@
+use glam::UVec2;
+
+use crate::{atlas::TextureAtlas, mesh::TexturedVertex, renderer::TextureHandle};
+
+/// A monospace font stored as a fixed grid of glyphs in a texture.
+///
+/// The atlas must lay out printable ASCII (`' '` to `'~'`, plus one trailing
+/// cell) row by row in `COLUMNS` x `ROWS` equally sized cells, so the cell size
+/// is derived from the texture size.
+pub struct BitmapFont {
+    pub(crate) texture: TextureHandle,
+}
+
+impl BitmapFont {
+    pub const COLUMNS: u32 = 16;
+    pub const ROWS: u32 = 6;
+    pub const FIRST_CHAR: char = ' ';
+    // Drawn in place of characters the atlas doesn't have
+    pub const FALLBACK_CHAR: char = '?';
+
+    pub fn new(texture: TextureHandle) -> Self {
+        Self { texture }
+    }
+
+    /// Width over height of a single glyph cell. Scale text transforms by
+    /// `(height * aspect, height)` to keep glyphs undistorted.
+    pub fn glyph_aspect(&self) -> f32 {
+        let cell_width = self.texture.width() as f32 / Self::COLUMNS as f32;
+        let cell_height = self.texture.height() as f32 / Self::ROWS as f32;
+        cell_width / cell_height
+    }
+
+    fn glyph_cell(c: char) -> (u32, u32) {
+        let count = Self::COLUMNS * Self::ROWS;
+        let index = (c as u32)
+            .checked_sub(Self::FIRST_CHAR as u32)
+            .filter(|&index| index < count)
+            .unwrap_or(Self::FALLBACK_CHAR as u32 - Self::FIRST_CHAR as u32);
+        (index % Self::COLUMNS, index / Self::COLUMNS)
+    }
+
+    /// Builds one quad per character. Each glyph occupies a unit cell in local
+    /// space, advancing one unit to the right per character and one unit down
+    /// per line.
+    pub fn layout(text: &str) -> (Vec<TexturedVertex>, Vec<u16>) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let (mut x, mut y) = (0.0, 0.0);
+
+        for c in text.chars() {
+            if c == '\n' {
+                x = 0.0;
+                y += 1.0;
+                continue;
+            }
+            if vertices.len() + 4 > u16::MAX as usize {
+                log::warn!("Text is too long, truncating");
+                break;
+            }
+
+            let (column, row) = Self::glyph_cell(c);
+            let (uv_min, uv_max) =
+                TextureAtlas::frame(column, row, UVec2::new(Self::COLUMNS, Self::ROWS));
+            let [u0, v0] = uv_min.to_array();
+            let [u1, v1] = uv_max.to_array();
+
+            let base = vertices.len() as u16;
+            // Same corner order and winding as the pre-baked square
+            for (position, uv) in [
+                ([x, y], [u0, v0]),             // Top Left
+                ([x, y + 1.0], [u0, v1]),       // Bottom Left
+                ([x + 1.0, y + 1.0], [u1, v1]), // Bottom Right
+                ([x + 1.0, y], [u1, v0]),       // Top Right
+            ] {
+                vertices.push(TexturedVertex {
+                    position: [position[0], position[1], 0.0],
+                    color: [1.0, 1.0, 1.0],
+                    uv,
+                });
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base + 3, base, base + 2]);
+
+            x += 1.0;
+        }
+
+        (vertices, indices)
+    }
+}
@@ -0,0 +1,195 @@
+use glam::Vec2;
+
+use crate::color::EngineColor;
+
+/// Values a `Tween` can animate between.
+pub trait Lerp: Copy {
+    /// `self` at `t = 0`, `other` at `t = 1`. Easings like `Bounce` stay in
+    /// 0..1, but the value isn't clamped, so others could overshoot.
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Vec2 {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for EngineColor {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        EngineColor::lerp(self, other, t)
+    }
+}
+
+/// How a tween's progress maps onto the blend between its ends. Every easing
+/// goes from 0 at the start to 1 at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    /// Starts slow and speeds up.
+    EaseIn,
+    /// Starts fast and slows down.
+    EaseOut,
+    EaseInOut,
+    /// Reaches the end and bounces back off it a few times, like a dropped ball.
+    Bounce,
+}
+
+impl Easing {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - 2.0 * (1.0 - t) * (1.0 - t)
+                }
+            }
+            Easing::Bounce => Self::bounce_out(t),
+        }
+    }
+
+    // Four parabolic arcs, each a quarter as high as the one before
+    fn bounce_out(t: f32) -> f32 {
+        const N: f32 = 7.5625;
+        const D: f32 = 2.75;
+        if t < 1.0 / D {
+            N * t * t
+        } else if t < 2.0 / D {
+            let t = t - 1.5 / D;
+            N * t * t + 0.75
+        } else if t < 2.5 / D {
+            let t = t - 2.25 / D;
+            N * t * t + 0.9375
+        } else {
+            let t = t - 2.625 / D;
+            N * t * t + 0.984375
+        }
+    }
+}
+
+/// Animates a value from `start` to `end` over `duration` seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Tween<T: Lerp> {
+    start: T,
+    end: T,
+    duration: f32,
+    elapsed: f32,
+    easing: Easing,
+}
+
+impl<T: Lerp> Tween<T> {
+    pub fn new(start: T, end: T, duration: f32, easing: Easing) -> Self {
+        Self {
+            start,
+            end,
+            duration,
+            elapsed: 0.0,
+            easing,
+        }
+    }
+
+    /// Advances the tween. It holds at `end` once the duration has passed.
+    pub fn update(&mut self, delta_time: f32) {
+        self.elapsed = (self.elapsed + delta_time).min(self.duration);
+    }
+
+    pub fn value(&self) -> T {
+        self.start
+            .lerp(self.end, self.easing.apply(self.progress()))
+    }
+
+    /// How far along the tween is, from 0 to 1, before easing.
+    pub fn progress(&self) -> f32 {
+        if self.duration <= 0.0 {
+            1.0
+        } else {
+            self.elapsed / self.duration
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+
+    /// Plays the tween again from `start`.
+    pub fn restart(&mut self) {
+        self.elapsed = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Easing; 5] = [
+        Easing::Linear,
+        Easing::EaseIn,
+        Easing::EaseOut,
+        Easing::EaseInOut,
+        Easing::Bounce,
+    ];
+
+    #[test]
+    fn easings_start_at_zero_and_end_at_one() {
+        for easing in ALL {
+            assert!(easing.apply(0.0).abs() < 1e-5, "{easing:?}");
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-5, "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn easings_clamp_their_input() {
+        for easing in ALL {
+            assert_eq!(easing.apply(-1.0), easing.apply(0.0));
+            assert_eq!(easing.apply(2.0), easing.apply(1.0));
+        }
+    }
+
+    #[test]
+    fn ease_in_lags_and_ease_out_leads_linear() {
+        assert!(Easing::EaseIn.apply(0.3) < 0.3);
+        assert!(Easing::EaseOut.apply(0.3) > 0.3);
+        assert!((Easing::EaseInOut.apply(0.5) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bounce_stays_in_range() {
+        for step in 0..=100 {
+            let value = Easing::Bounce.apply(step as f32 / 100.0);
+            assert!((0.0..=1.0 + 1e-5).contains(&value));
+        }
+    }
+
+    #[test]
+    fn tween_reaches_and_holds_its_end() {
+        let mut tween = Tween::new(Vec2::ZERO, Vec2::new(4.0, 2.0), 2.0, Easing::Linear);
+        tween.update(1.0);
+        assert_eq!(tween.value(), Vec2::new(2.0, 1.0));
+        assert!(!tween.is_finished());
+
+        tween.update(5.0);
+        assert_eq!(tween.value(), Vec2::new(4.0, 2.0));
+        assert!(tween.is_finished());
+
+        tween.restart();
+        assert_eq!(tween.progress(), 0.0);
+    }
+
+    #[test]
+    fn zero_duration_tween_is_already_finished() {
+        let tween = Tween::new(1.0, 3.0, 0.0, Easing::EaseIn);
+        assert!(tween.is_finished());
+        assert_eq!(tween.value(), 3.0);
+    }
+}